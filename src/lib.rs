@@ -1,26 +1,36 @@
 use std::{
     collections::HashMap,
     fmt::Write as _,
-    fs::{self, OpenOptions},
+    fs::{self, File, OpenOptions, TryLockError},
+    hash::{DefaultHasher, Hash as _, Hasher as _},
     io::Write as _,
     path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
 use thiserror::Error;
 use wayland_client::{
-    Connection, Dispatch, Proxy, QueueHandle,
+    Connection, Dispatch, EventQueue, Proxy, QueueHandle,
     backend::ObjectId,
     globals::{BindError, GlobalListContents, registry_queue_init},
     protocol::{wl_callback, wl_output, wl_registry},
 };
 use wayland_protocols_wlr::output_management::v1::client::{
+    zwlr_output_configuration_head_v1::{self, ZwlrOutputConfigurationHeadV1},
+    zwlr_output_configuration_v1::{self, ZwlrOutputConfigurationV1},
     zwlr_output_head_v1::{self, ZwlrOutputHeadV1},
     zwlr_output_manager_v1::{self, ZwlrOutputManagerV1},
     zwlr_output_mode_v1::{self, ZwlrOutputModeV1},
 };
+use xattr::FileExt as _;
 
-const PROFILE_KEYWORD: &[u8] = b"profile";
+/// Largest refresh-rate difference (in mHz) still treated as the same mode
+/// when resolving a requested mode against the modes a head advertises.
+const MODE_REFRESH_TOLERANCE_MHZ: i32 = 1000;
+
+const CONFIG_LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UpsertOutcome {
@@ -28,10 +38,63 @@ pub enum UpsertOutcome {
     AppendedNew,
 }
 
+/// How config rewrites are carried out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigWriteOptions {
+    /// Number of timestamped backups of the previous config to keep; `0`
+    /// (the default) disables backups.
+    pub backup_count: usize,
+    /// How long to wait for another process to release the config lock.
+    pub lock_timeout: Duration,
+    /// How often to redo the merge on fresh content when the config is
+    /// modified between reading and replacing it; `0` fails instead.
+    pub modification_retries: usize,
+}
+
+impl Default for ConfigWriteOptions {
+    fn default() -> Self {
+        Self {
+            backup_count: 0,
+            lock_timeout: Duration::from_secs(5),
+            modification_retries: 0,
+        }
+    }
+}
+
+/// Device and inode of a file, where the platform has them.
+type FileIdentity = Option<(u64, u64)>;
+
+/// Identity and content of the config file at the time it was read.
+#[derive(Debug, PartialEq, Eq)]
+struct ConfigFingerprint {
+    /// File identity, length and modification time; `None` if the file did
+    /// not exist.
+    metadata: Option<(FileIdentity, u64, Option<std::time::SystemTime>)>,
+    content_hash: u64,
+}
+
+/// A copy of the kanshi config taken before it was rewritten.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigBackup {
+    path: PathBuf,
+    timestamp: String,
+}
+
+/// A config update that has been computed but not written.
+#[derive(Debug, Clone)]
+pub struct ConfigPreview {
+    path: PathBuf,
+    current: String,
+    updated: String,
+    outcome: UpsertOutcome,
+}
+
 #[derive(Debug, Error)]
 pub enum GenerateError {
     #[error("failed to parse input output JSON")]
     ParseJson(#[source] serde_json::Error),
+    #[error("failed to serialize output state as JSON")]
+    SerializeJson(#[source] serde_json::Error),
     #[error("profile name cannot be empty")]
     EmptyProfileName,
     #[error("output `{output}` is enabled but has no current or preferred mode")]
@@ -42,6 +105,29 @@ pub enum GenerateError {
     MissingScale { output: String },
     #[error("failed to format kanshi profile")]
     Format,
+    #[error("output `{output}` reports no make, model or serial to match by description")]
+    MissingDescription { output: String },
+    #[error("output `{output}` reports no make and model to match by")]
+    MissingMakeModel { output: String },
+    #[error("--only/--exclude leave no outputs to write")]
+    NoOutputsSelected,
+    #[error("output `{output}` is not connected")]
+    UnknownOutput { output: String },
+    #[error(
+        "outputs {} would all become `*`, but a kanshi profile can have at most one wildcard output",
+        .outputs.join(", ")
+    )]
+    MultipleWildcards { outputs: Vec<String> },
+    #[error("no external output is connected to write as `*`")]
+    NoExternalOutput,
+    #[error(
+        "outputs {} share the identifier `{identifier}` and cannot be told apart",
+        .outputs.join(", ")
+    )]
+    AmbiguousOutputIdentifier {
+        identifier: String,
+        outputs: Vec<String>,
+    },
     #[error("could not resolve default kanshi config path: set XDG_CONFIG_HOME or HOME")]
     ConfigPathUnavailable,
     #[error("failed to read kanshi config `{path}`")]
@@ -70,6 +156,128 @@ pub enum GenerateError {
     WaylandProtocolError { details: String },
     #[error("timed out waiting for initial output-management state sync")]
     WaylandSyncTimeout,
+    #[error("profile `{profile_name}` not found in kanshi config")]
+    ProfileNotFound { profile_name: String },
+    #[error("profile output `{criteria}` does not match any connected output")]
+    ProfileOutputNotConnected { criteria: String },
+    #[error(
+        "output `{output}` does not advertise mode {mode}; use `mode --custom` to set it anyway"
+    )]
+    UnadvertisedMode { output: String, mode: String },
+    #[error("mode {mode} for output `{output}` is too large")]
+    ModeOutOfRange { output: String, mode: String },
+    #[error("compositor rejected the output configuration")]
+    OutputConfigurationFailed,
+    #[error(
+        "compositor cancelled the output configuration because the output state changed; try again"
+    )]
+    OutputConfigurationCancelled,
+    #[error("compositor stopped output management before answering the output configuration")]
+    OutputManagerFinished,
+    #[error("compositor rejected the output configuration for {}", .outputs.join(", "))]
+    OutputConfigurationRejected { outputs: Vec<String> },
+    #[error("profile `{profile_name}` already exists in kanshi config")]
+    ProfileAlreadyExists { profile_name: String },
+    #[error(
+        "kanshi config `{path}` is locked by another process (waited {}s)",
+        .timeout.as_secs_f64()
+    )]
+    ConfigLocked { path: String, timeout: Duration },
+    #[error(
+        "kanshi config `{path}` was modified by another program while it was being updated; nothing was written"
+    )]
+    ConfigModifiedConcurrently { path: String },
+    #[error("failed to back up kanshi config to `{path}`")]
+    ConfigBackup {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+}
+
+/// Configuration syntaxes that output snapshots can be rendered as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A kanshi `profile` block.
+    Kanshi,
+    /// sway `output` commands, one per output.
+    Sway,
+    /// Hyprland `monitor=` lines, one per output.
+    Hyprland,
+    /// niri KDL `output` blocks.
+    Niri,
+    /// A single `wlr-randr` invocation configuring every output.
+    WlrRandr,
+}
+
+/// How generated kanshi profiles identify each output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchBy {
+    /// Make, model and serial when any of them is known and no other output
+    /// shares them, otherwise the connector name.
+    #[default]
+    Auto,
+    /// Make, model and serial; fails for outputs that report none of them.
+    Description,
+    /// The connector name, e.g. `DP-1`.
+    Connector,
+    /// Make and model followed by a `*` wildcard matching any serial, so one
+    /// profile fits every unit of the same monitor model.
+    MakeModel,
+}
+
+/// Outputs whose kanshi criteria are replaced by `*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Wildcard {
+    /// Every output that is not a built-in panel (`eDP`, `LVDS` or `DSI`
+    /// connector), e.g. for a "laptop + any external monitor" profile.
+    External,
+    /// The output with this connector name or identifier.
+    Output(String),
+}
+
+/// How refresh rates are written in kanshi modes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RefreshPrecision {
+    /// Two decimals, e.g. `59.95Hz`.
+    #[default]
+    TwoDecimals,
+    /// Three decimals, e.g. `59.951Hz` or `60.000Hz`.
+    ThreeDecimals,
+    /// The exact millihertz value without trailing zeros, e.g. `59.951Hz` or `60Hz`.
+    Exact,
+    /// Whole hertz, e.g. `60Hz`.
+    Rounded,
+}
+
+/// How scale factors are written in kanshi profiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleFormat {
+    /// Two decimals, e.g. `1.33`.
+    #[default]
+    Fixed,
+    /// The shortest representation of the exact value, e.g. `1.333333` or `1`.
+    Shortest,
+}
+
+/// Options that change how outputs are rendered.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderOptions {
+    pub match_by: MatchBy,
+    pub wildcard: Option<Wildcard>,
+    pub refresh_precision: RefreshPrecision,
+    pub scale_format: ScaleFormat,
+}
+
+/// JSON schemas accepted as captured output state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    /// The schema of [`OutputSnapshot`], as written by `wlr-randr --json`.
+    Wlr,
+    /// `swaymsg -t get_outputs --raw`.
+    Sway,
+    /// `hyprctl monitors all -j`.
+    Hyprland,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -77,6 +285,73 @@ pub enum GenerateError {
 struct WlrStatus(Vec<OutputSnapshot>);
 
 #[derive(Debug, Clone, Deserialize)]
+struct SwayOutput {
+    name: String,
+    #[serde(default)]
+    make: String,
+    #[serde(default)]
+    model: String,
+    serial: Option<String>,
+    #[serde(default)]
+    active: bool,
+    #[serde(default)]
+    modes: Vec<SwayMode>,
+    current_mode: Option<SwayMode>,
+    rect: Option<SwayRect>,
+    scale: Option<f64>,
+    transform: Option<String>,
+    /// `enabled` or `disabled`; absent on outputs that do not support it.
+    adaptive_sync_status: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct SwayMode {
+    width: u32,
+    height: u32,
+    /// Refresh rate in mHz.
+    refresh: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct SwayRect {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct HyprlandMonitor {
+    name: String,
+    #[serde(default)]
+    make: String,
+    #[serde(default)]
+    model: String,
+    serial: Option<String>,
+    #[serde(default)]
+    width: u32,
+    #[serde(default)]
+    height: u32,
+    #[serde(default)]
+    refresh_rate: f64,
+    #[serde(default)]
+    x: i32,
+    #[serde(default)]
+    y: i32,
+    scale: Option<f64>,
+    /// `wl_output.transform` value.
+    #[serde(default)]
+    transform: u32,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    disabled: bool,
+    vrr: Option<bool>,
+    /// Modes formatted as `WIDTHxHEIGHT@REFRESHHz`.
+    #[serde(default)]
+    available_modes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct OutputSnapshot {
     name: String,
     #[serde(default)]
@@ -90,10 +365,30 @@ pub struct OutputSnapshot {
     position: Option<PositionSnapshot>,
     scale: Option<f64>,
     transform: Option<String>,
+    /// Variable refresh rate state; `None` if the source does not report it.
+    #[serde(default)]
+    adaptive_sync: Option<bool>,
+    /// Human-readable description reported by the compositor. Captured
+    /// only: kanshi never matches against it, and wlroots appends the
+    /// connector name to it.
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_physical_size")]
+    physical_size: Option<PhysicalSizeSnapshot>,
 }
 
 impl OutputSnapshot {
+    /// Physical dimensions of the output, if the compositor knows them.
+    pub fn physical_size(&self) -> Option<&PhysicalSizeSnapshot> {
+        self.physical_size.as_ref()
+    }
+
     fn identifier(&self) -> String {
+        self.description().unwrap_or_else(|| self.name.clone())
+    }
+
+    /// Make, model and serial joined by spaces, if any of them is known.
+    fn description(&self) -> Option<String> {
         let mut segments = Vec::with_capacity(3);
         if !self.make.trim().is_empty() {
             segments.push(self.make.as_str());
@@ -108,12 +403,82 @@ impl OutputSnapshot {
         }
 
         if segments.is_empty() {
-            self.name.clone()
+            None
         } else {
-            segments.join(" ")
+            Some(segments.join(" "))
+        }
+    }
+
+    /// Criteria a kanshi profile uses to match this output.
+    fn criteria(&self, match_by: MatchBy) -> Result<String, GenerateError> {
+        match match_by {
+            MatchBy::Auto => Ok(self.identifier()),
+            MatchBy::Description => {
+                self.description()
+                    .ok_or_else(|| GenerateError::MissingDescription {
+                        output: self.name.clone(),
+                    })
+            }
+            MatchBy::Connector => Ok(self.name.clone()),
+            MatchBy::MakeModel => {
+                if self.make.trim().is_empty() || self.model.trim().is_empty() {
+                    return Err(GenerateError::MissingMakeModel {
+                        output: self.name.clone(),
+                    });
+                }
+                Ok(format!("{} {} *", self.make, self.model))
+            }
         }
     }
 
+    /// Identifier in the form sway and niri match against: make, model and
+    /// serial, with `Unknown` standing in for missing fields.
+    fn padded_identifier(&self) -> String {
+        let known = |value: &str| !value.trim().is_empty();
+        let serial = self.serial.as_deref().filter(|serial| known(serial));
+        if !known(&self.make) && !known(&self.model) && serial.is_none() {
+            return self.name.clone();
+        }
+
+        let or_unknown = |value: &str| {
+            if known(value) {
+                value.to_owned()
+            } else {
+                String::from("Unknown")
+            }
+        };
+        format!(
+            "{} {} {}",
+            or_unknown(&self.make),
+            or_unknown(&self.model),
+            or_unknown(serial.unwrap_or_default())
+        )
+    }
+
+    /// Mode, position and scale of an enabled output, all of which every
+    /// renderer needs.
+    fn enabled_state(&self) -> Result<EnabledState<'_>, GenerateError> {
+        let mode = self
+            .active_mode()
+            .ok_or_else(|| GenerateError::MissingMode {
+                output: self.name.clone(),
+            })?;
+        let position = self
+            .position
+            .as_ref()
+            .ok_or_else(|| GenerateError::MissingPosition {
+                output: self.name.clone(),
+            })?;
+        let scale = self.scale.ok_or_else(|| GenerateError::MissingScale {
+            output: self.name.clone(),
+        })?;
+        Ok(EnabledState {
+            mode,
+            position,
+            scale,
+        })
+    }
+
     fn active_mode(&self) -> Option<&ModeSnapshot> {
         self.modes
             .iter()
@@ -124,54 +489,402 @@ impl OutputSnapshot {
     fn normalized_transform(&self) -> Option<&'static str> {
         normalize_transform_str(self.transform.as_deref()?)
     }
+
+    /// Whether `selector` names this output by connector or identifier.
+    fn is_selected_by(&self, selector: &str) -> bool {
+        self.name == selector || self.identifier() == selector
+    }
+
+    /// Whether this is a built-in panel, judged by its connector type.
+    fn is_internal(&self) -> bool {
+        ["eDP-", "LVDS-", "DSI-"]
+            .iter()
+            .any(|prefix| self.name.starts_with(prefix))
+    }
+
+    fn matches_criteria(&self, criteria: &str) -> bool {
+        criteria == self.name
+            || criteria == self.padded_identifier()
+            || glob_matches(criteria, &self.padded_identifier())
+    }
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl From<SwayOutput> for OutputSnapshot {
+    fn from(output: SwayOutput) -> Self {
+        let mut modes = output
+            .modes
+            .iter()
+            .map(|mode| ModeSnapshot {
+                width: mode.width,
+                height: mode.height,
+                refresh: f64::from(mode.refresh) / 1000.0,
+                preferred: false,
+                current: output.active && output.current_mode.as_ref() == Some(mode),
+                custom_mode: false,
+            })
+            .collect::<Vec<_>>();
+        if let Some(current_mode) = output.current_mode.as_ref()
+            && output.active
+            && !output.modes.contains(current_mode)
+        {
+            modes.push(ModeSnapshot {
+                width: current_mode.width,
+                height: current_mode.height,
+                refresh: f64::from(current_mode.refresh) / 1000.0,
+                preferred: false,
+                current: true,
+                // Outputs without a mode list (e.g. headless) accept any mode.
+                custom_mode: !output.modes.is_empty(),
+            });
+        }
+
+        Self {
+            name: output.name,
+            make: known_sway_value(Some(output.make)).unwrap_or_default(),
+            model: known_sway_value(Some(output.model)).unwrap_or_default(),
+            serial: known_sway_value(output.serial),
+            enabled: output.active,
+            modes,
+            position: output
+                .rect
+                .filter(|_| output.active)
+                .map(|rect| PositionSnapshot {
+                    x: rect.x,
+                    y: rect.y,
+                }),
+            scale: output.scale.filter(|_| output.active),
+            transform: output.transform,
+            adaptive_sync: output
+                .adaptive_sync_status
+                .as_deref()
+                .and_then(|status| match status {
+                    "enabled" => Some(true),
+                    "disabled" => Some(false),
+                    _ => None,
+                }),
+            description: None,
+            physical_size: None,
+        }
+    }
+}
+
+impl From<HyprlandMonitor> for OutputSnapshot {
+    fn from(monitor: HyprlandMonitor) -> Self {
+        let enabled = !monitor.disabled;
+        let mut modes = monitor
+            .available_modes
+            .iter()
+            .filter_map(|mode| parse_mode_setting(mode, false))
+            .map(|mode| ModeSnapshot {
+                width: mode.width,
+                height: mode.height,
+                refresh: mode.refresh.unwrap_or_default(),
+                preferred: false,
+                current: false,
+                custom_mode: false,
+            })
+            .collect::<Vec<_>>();
+
+        let advertises_modes = !modes.is_empty();
+        if enabled && monitor.width > 0 && monitor.height > 0 {
+            // availableModes rounds to two decimals; keep the exact current rate.
+            let current = modes.iter_mut().find(|mode| {
+                mode.width == monitor.width
+                    && mode.height == monitor.height
+                    && (mode.refresh - monitor.refresh_rate).abs() < 0.01
+            });
+            match current {
+                Some(mode) => {
+                    mode.refresh = monitor.refresh_rate;
+                    mode.current = true;
+                }
+                None => modes.push(ModeSnapshot {
+                    width: monitor.width,
+                    height: monitor.height,
+                    refresh: monitor.refresh_rate,
+                    preferred: false,
+                    current: true,
+                    // Older hyprctl versions do not list availableModes.
+                    custom_mode: advertises_modes,
+                }),
+            }
+        }
+
+        Self {
+            name: monitor.name,
+            make: monitor.make,
+            model: monitor.model,
+            serial: monitor.serial.filter(|serial| !serial.trim().is_empty()),
+            enabled,
+            modes,
+            position: enabled.then_some(PositionSnapshot {
+                x: monitor.x,
+                y: monitor.y,
+            }),
+            scale: monitor.scale.filter(|_| enabled),
+            transform: normalize_transform_u32(monitor.transform).map(String::from),
+            adaptive_sync: monitor.vrr,
+            description: Some(monitor.description).filter(|description| !description.is_empty()),
+            physical_size: None,
+        }
+    }
+}
+
+/// sway reports missing EDID fields as the literal string `Unknown`.
+fn known_sway_value(value: Option<String>) -> Option<String> {
+    value.filter(|value| value != "Unknown")
+}
+
+struct EnabledState<'a> {
+    mode: &'a ModeSnapshot,
+    position: &'a PositionSnapshot,
+    scale: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct ModeSnapshot {
     width: u32,
     height: u32,
     refresh: f64,
     preferred: bool,
     current: bool,
+    /// The output does not advertise this mode, so it has to be set as a
+    /// custom mode.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    custom_mode: bool,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct PositionSnapshot {
     x: i32,
     y: i32,
 }
 
-#[derive(Debug)]
-struct ProfileBlock {
-    name: String,
+/// Physical dimensions of an output in millimetres.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PhysicalSizeSnapshot {
+    width: i32,
+    height: i32,
+}
+
+impl PhysicalSizeSnapshot {
+    /// `None` for the 0x0 size projectors and virtual outputs report.
+    fn known(width: i32, height: i32) -> Option<Self> {
+        (width > 0 && height > 0).then_some(Self { width, height })
+    }
+
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+}
+
+fn deserialize_physical_size<'de, D>(
+    deserializer: D,
+) -> Result<Option<PhysicalSizeSnapshot>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let size = Option::<PhysicalSizeSnapshot>::deserialize(deserializer)?;
+    Ok(size.and_then(|size| PhysicalSizeSnapshot::known(size.width, size.height)))
+}
+
+/// Output configuration of a single kanshi profile, ready to be applied.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileSettings {
+    defaults: Vec<OutputSettings>,
+    outputs: Vec<OutputSettings>,
+}
+
+/// Desired state of one output; unset properties keep their current value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutputSettings {
+    criteria: String,
+    enabled: Option<bool>,
+    mode: Option<ModeSetting>,
+    position: Option<PositionSnapshot>,
+    scale: Option<f64>,
+    transform: Option<String>,
+    adaptive_sync: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct ModeSetting {
+    width: u32,
+    height: u32,
+    refresh: Option<f64>,
+    custom: bool,
+}
+
+impl ModeSetting {
+    /// Width and height as the protocol's signed integers.
+    fn protocol_size(&self, output_name: &str) -> Result<(i32, i32), GenerateError> {
+        match (i32::try_from(self.width), i32::try_from(self.height)) {
+            (Ok(width), Ok(height)) => Ok((width, height)),
+            _ => Err(GenerateError::ModeOutOfRange {
+                output: output_name.to_owned(),
+                mode: self.to_string(),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for ModeSetting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)?;
+        if let Some(refresh) = self.refresh {
+            write!(f, "@{refresh}Hz")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigurationMode {
+    Apply,
+    Test,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConfigurationResult {
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// Byte range of a node within the source text of a [`KanshiConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
     start: usize,
     end: usize,
 }
 
+impl Span {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+}
+
+/// Lossless syntax tree of a kanshi config file.
+///
+/// Every byte of the source is covered by exactly one top-level item, so
+/// rendering the tree reproduces the input byte-for-byte.
+#[derive(Debug, Clone)]
+pub struct KanshiConfig {
+    source: String,
+    items: Vec<ConfigItem>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ConfigItem {
+    Whitespace(Span),
+    Comment(Span),
+    Output(OutputDirective),
+    Profile(ProfileBlock),
+    Include(IncludeDirective),
+    Unknown(Directive),
+}
+
+#[derive(Debug, Clone)]
+pub enum ProfileItem {
+    Whitespace(Span),
+    Comment(Span),
+    Output(OutputDirective),
+    Exec(ExecDirective),
+    Unknown(Directive),
+}
+
+/// A bare or quoted token; `value` has quotes and escapes removed.
+#[derive(Debug, Clone)]
+pub struct Word {
+    value: String,
+    span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProfileBlock {
+    name: Option<Word>,
+    body: Span,
+    items: Vec<ProfileItem>,
+    span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputDirective {
+    criteria: Word,
+    arguments: Vec<Word>,
+    span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExecDirective {
+    command: String,
+    span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub struct IncludeDirective {
+    path: Word,
+    span: Span,
+}
+
+/// A directive this parser does not know about, kept verbatim.
+#[derive(Debug, Clone)]
+pub struct Directive {
+    keyword: Word,
+    arguments: Vec<Word>,
+    span: Span,
+}
+
+struct ConfigParser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+struct WaylandSession {
+    event_queue: EventQueue<WaylandState>,
+    state: WaylandState,
+    manager: ZwlrOutputManagerV1,
+}
+
 #[derive(Debug, Default)]
 struct WaylandState {
     done_received: bool,
     finished: bool,
+    serial: Option<u32>,
+    configuration_result: Option<ConfigurationResult>,
     heads: HashMap<ObjectId, WaylandHeadState>,
     modes: HashMap<ObjectId, WaylandModeState>,
 }
 
 #[derive(Debug, Default)]
 struct WaylandHeadState {
+    proxy: Option<ZwlrOutputHeadV1>,
     name: Option<String>,
     make: Option<String>,
     model: Option<String>,
     serial: Option<String>,
+    description: Option<String>,
+    physical_size: Option<PhysicalSizeSnapshot>,
     enabled: Option<bool>,
     current_mode: Option<ObjectId>,
     position: Option<PositionSnapshot>,
     scale: Option<f64>,
     transform: Option<String>,
+    adaptive_sync: Option<bool>,
     mode_ids: Vec<ObjectId>,
 }
 
 #[derive(Debug, Default)]
 struct WaylandModeState {
+    proxy: Option<ZwlrOutputModeV1>,
     width: Option<i32>,
     height: Option<i32>,
     refresh_mhz: Option<i32>,
@@ -213,10 +926,12 @@ impl Dispatch<ZwlrOutputManagerV1, ()> for WaylandState {
     ) {
         match event {
             zwlr_output_manager_v1::Event::Head { head } => {
-                state.heads.entry(head.id()).or_default();
+                let head_id = head.id();
+                state.heads.entry(head_id).or_default().proxy = Some(head);
             }
-            zwlr_output_manager_v1::Event::Done { .. } => {
+            zwlr_output_manager_v1::Event::Done { serial } => {
                 state.done_received = true;
+                state.serial = Some(serial);
             }
             zwlr_output_manager_v1::Event::Finished => {
                 state.finished = true;
@@ -254,12 +969,18 @@ impl Dispatch<ZwlrOutputHeadV1, ()> for WaylandState {
             zwlr_output_head_v1::Event::SerialNumber { serial_number } => {
                 head_state.serial = Some(serial_number);
             }
+            zwlr_output_head_v1::Event::Description { description } => {
+                head_state.description = Some(description);
+            }
+            zwlr_output_head_v1::Event::PhysicalSize { width, height } => {
+                head_state.physical_size = PhysicalSizeSnapshot::known(width, height);
+            }
             zwlr_output_head_v1::Event::Enabled { enabled } => {
                 head_state.enabled = Some(enabled != 0);
             }
             zwlr_output_head_v1::Event::Mode { mode } => {
                 let mode_id = mode.id();
-                state.modes.entry(mode_id.clone()).or_default();
+                state.modes.entry(mode_id.clone()).or_default().proxy = Some(mode);
                 if !head_state.mode_ids.contains(&mode_id) {
                     head_state.mode_ids.push(mode_id);
                 }
@@ -276,6 +997,17 @@ impl Dispatch<ZwlrOutputHeadV1, ()> for WaylandState {
             zwlr_output_head_v1::Event::Transform { transform } => {
                 head_state.transform = transform_from_wayland(transform);
             }
+            zwlr_output_head_v1::Event::AdaptiveSync { state } => {
+                head_state.adaptive_sync = match state {
+                    wayland_client::WEnum::Value(
+                        zwlr_output_head_v1::AdaptiveSyncState::Enabled,
+                    ) => Some(true),
+                    wayland_client::WEnum::Value(
+                        zwlr_output_head_v1::AdaptiveSyncState::Disabled,
+                    ) => Some(false),
+                    _ => None,
+                };
+            }
             zwlr_output_head_v1::Event::Finished => {
                 state.heads.remove(&head.id());
             }
@@ -317,62 +1049,493 @@ impl Dispatch<ZwlrOutputModeV1, ()> for WaylandState {
     }
 }
 
-pub fn collect_outputs_wayland() -> Result<Vec<OutputSnapshot>, GenerateError> {
-    let connection =
-        Connection::connect_to_env().map_err(|source| GenerateError::WaylandConnect {
-            details: source.to_string(),
-        })?;
+impl Dispatch<ZwlrOutputConfigurationV1, ()> for WaylandState {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrOutputConfigurationV1,
+        event: zwlr_output_configuration_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        state.configuration_result = match event {
+            zwlr_output_configuration_v1::Event::Succeeded => Some(ConfigurationResult::Succeeded),
+            zwlr_output_configuration_v1::Event::Failed => Some(ConfigurationResult::Failed),
+            zwlr_output_configuration_v1::Event::Cancelled => Some(ConfigurationResult::Cancelled),
+            _ => return,
+        };
+    }
+}
+
+impl Dispatch<ZwlrOutputConfigurationHeadV1, ()> for WaylandState {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrOutputConfigurationHeadV1,
+        _: zwlr_output_configuration_head_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
 
-    let (globals, mut event_queue) =
-        registry_queue_init::<WaylandState>(&connection).map_err(|source| {
-            GenerateError::WaylandProtocolError {
+impl WaylandSession {
+    fn connect() -> Result<Self, GenerateError> {
+        let connection =
+            Connection::connect_to_env().map_err(|source| GenerateError::WaylandConnect {
                 details: source.to_string(),
-            }
-        })?;
-    let qh = event_queue.handle();
+            })?;
 
-    let manager = globals
-        .bind::<ZwlrOutputManagerV1, _, _>(&qh, 1..=4, ())
-        .map_err(map_bind_error)?;
+        let (globals, event_queue) =
+            registry_queue_init::<WaylandState>(&connection).map_err(|source| {
+                GenerateError::WaylandProtocolError {
+                    details: source.to_string(),
+                }
+            })?;
+        let qh = event_queue.handle();
 
-    let mut state = WaylandState::default();
-    let mut synced = false;
-    for _ in 0..3 {
-        event_queue.roundtrip(&mut state).map_err(|source| {
-            GenerateError::WaylandProtocolError {
-                details: source.to_string(),
-            }
-        })?;
+        let manager = globals
+            .bind::<ZwlrOutputManagerV1, _, _>(&qh, 1..=4, ())
+            .map_err(map_bind_error)?;
+
+        let mut session = Self {
+            event_queue,
+            state: WaylandState::default(),
+            manager,
+        };
+        for _ in 0..3 {
+            session.roundtrip()?;
 
-        if state.done_received || state.finished {
-            synced = true;
-            break;
+            if session.state.done_received || session.state.finished {
+                return Ok(session);
+            }
         }
-    }
 
-    manager.stop();
+        session.manager.stop();
+        Err(GenerateError::WaylandSyncTimeout)
+    }
 
-    if !synced {
-        return Err(GenerateError::WaylandSyncTimeout);
+    fn roundtrip(&mut self) -> Result<(), GenerateError> {
+        self.event_queue
+            .roundtrip(&mut self.state)
+            .map(|_| ())
+            .map_err(|source| GenerateError::WaylandProtocolError {
+                details: source.to_string(),
+            })
     }
 
-    build_output_snapshots(&state)
-}
+    fn configure(
+        &mut self,
+        profile: &ProfileSettings,
+        mode: ConfigurationMode,
+    ) -> Result<(), GenerateError> {
+        let heads = build_head_snapshots(&self.state)?;
+        let outputs = heads
+            .iter()
+            .map(|(_, output)| output.clone())
+            .collect::<Vec<_>>();
+        let assignments = match_profile_to_heads(&outputs, profile)?;
+
+        let serial = self
+            .state
+            .serial
+            .ok_or_else(|| GenerateError::WaylandProtocolError {
+                details: String::from("output manager did not report a configuration serial"),
+            })?;
+        let qh = self.event_queue.handle();
+        let configuration = self.manager.create_configuration(serial, &qh, ());
+
+        for ((head_id, _), settings) in heads.iter().zip(&assignments) {
+            let head_state = &self.state.heads[head_id];
+            let Some(head) = head_state.proxy.as_ref() else {
+                continue;
+            };
+            let settings = settings.as_ref();
+            let enabled = settings
+                .and_then(|settings| settings.enabled)
+                .unwrap_or(head_state.enabled.unwrap_or(false));
+            if !enabled {
+                configuration.disable_head(head);
+                continue;
+            }
+
+            let head_configuration = configuration.enable_head(head, &qh, ());
+            let output_name = head_state.name.as_deref().unwrap_or("<unknown>");
+            let mode_set = match settings.and_then(|settings| settings.mode.as_ref()) {
+                // Like kanshi, only `--custom` modes may bypass the advertised list.
+                Some(mode) if !mode.custom => match self.find_mode(head_state, mode) {
+                    Some(proxy) => {
+                        head_configuration.set_mode(proxy);
+                        Ok(())
+                    }
+                    None => Err(GenerateError::UnadvertisedMode {
+                        output: output_name.to_owned(),
+                        mode: mode.to_string(),
+                    }),
+                },
+                Some(mode) => set_custom_mode(&head_configuration, mode, output_name),
+                None => {
+                    let fallback = head_state
+                        .current_mode
+                        .as_ref()
+                        .or_else(|| {
+                            head_state.mode_ids.iter().find(|mode_id| {
+                                self.state
+                                    .modes
+                                    .get(*mode_id)
+                                    .is_some_and(|mode| mode.preferred)
+                            })
+                        })
+                        .and_then(|mode_id| self.state.modes.get(mode_id)?.proxy.as_ref());
+                    if let Some(proxy) = fallback {
+                        head_configuration.set_mode(proxy);
+                    }
+                    Ok(())
+                }
+            };
+            if let Err(err) = mode_set {
+                configuration.destroy();
+                return Err(err);
+            }
+
+            let position = settings
+                .and_then(|settings| settings.position.as_ref())
+                .or(head_state.position.as_ref());
+            if let Some(position) = position {
+                head_configuration.set_position(position.x, position.y);
+            }
+            if let Some(scale) = settings
+                .and_then(|settings| settings.scale)
+                .or(head_state.scale)
+            {
+                head_configuration.set_scale(scale);
+            }
+            if let Some(transform) = settings
+                .and_then(|settings| settings.transform.as_deref())
+                .or(head_state.transform.as_deref())
+                .and_then(transform_to_wayland)
+            {
+                head_configuration.set_transform(transform);
+            }
+            if let Some(adaptive_sync) = settings.and_then(|settings| settings.adaptive_sync)
+                && self.manager.version() >= 4
+            {
+                head_configuration.set_adaptive_sync(if adaptive_sync {
+                    zwlr_output_head_v1::AdaptiveSyncState::Enabled
+                } else {
+                    zwlr_output_head_v1::AdaptiveSyncState::Disabled
+                });
+            }
+        }
+
+        match mode {
+            ConfigurationMode::Apply => configuration.apply(),
+            ConfigurationMode::Test => configuration.test(),
+        }
+
+        self.state.configuration_result = None;
+        let result = loop {
+            if let Some(result) = self.state.configuration_result.take() {
+                break Ok(result);
+            }
+            // A finished manager never answers the configuration.
+            if self.state.finished {
+                break Err(GenerateError::OutputManagerFinished);
+            }
+            match self.event_queue.blocking_dispatch(&mut self.state) {
+                Ok(0) => break Ok(ConfigurationResult::Cancelled),
+                Ok(_) => {}
+                Err(source) => {
+                    break Err(GenerateError::WaylandProtocolError {
+                        details: source.to_string(),
+                    });
+                }
+            }
+        };
+        configuration.destroy();
+        let result = result?;
+
+        match result {
+            ConfigurationResult::Succeeded => Ok(()),
+            ConfigurationResult::Failed => Err(GenerateError::OutputConfigurationFailed),
+            ConfigurationResult::Cancelled => Err(GenerateError::OutputConfigurationCancelled),
+        }
+    }
+
+    /// Tests `profile` and, if the compositor rejects it, tests each output on
+    /// its own to name the outputs whose mode/position/scale are not accepted.
+    fn verify(&mut self, profile: &ProfileSettings) -> Result<(), GenerateError> {
+        match self.configure(profile, ConfigurationMode::Test) {
+            Err(GenerateError::OutputConfigurationFailed) => {}
+            result => return result,
+        }
+
+        let mut rejected = Vec::new();
+        for output in &profile.outputs {
+            let single = ProfileSettings {
+                defaults: profile.defaults.clone(),
+                outputs: vec![output.clone()],
+            };
+            match self.configure(&single, ConfigurationMode::Test) {
+                Ok(()) => {}
+                Err(GenerateError::OutputConfigurationFailed) => rejected.push(output.describe()),
+                Err(error) => return Err(error),
+            }
+        }
+
+        if rejected.is_empty() {
+            Err(GenerateError::OutputConfigurationFailed)
+        } else {
+            Err(GenerateError::OutputConfigurationRejected { outputs: rejected })
+        }
+    }
+
+    fn find_mode<'a>(
+        &'a self,
+        head_state: &WaylandHeadState,
+        mode: &ModeSetting,
+    ) -> Option<&'a ZwlrOutputModeV1> {
+        let candidates = head_state
+            .mode_ids
+            .iter()
+            .filter_map(|mode_id| self.state.modes.get(mode_id))
+            .filter(|candidate| {
+                candidate.width == i32::try_from(mode.width).ok()
+                    && candidate.height == i32::try_from(mode.height).ok()
+            });
+
+        let best = match mode.refresh {
+            Some(refresh) => {
+                let requested = refresh_to_mhz(refresh);
+                candidates
+                    .filter_map(|candidate| {
+                        let distance = (candidate.refresh_mhz? - requested).abs();
+                        (distance <= MODE_REFRESH_TOLERANCE_MHZ).then_some((distance, candidate))
+                    })
+                    .min_by_key(|(distance, _)| *distance)
+                    .map(|(_, candidate)| candidate)
+            }
+            None => candidates.max_by_key(|candidate| (candidate.preferred, candidate.refresh_mhz)),
+        };
+        best?.proxy.as_ref()
+    }
+}
+
+pub fn collect_outputs_wayland() -> Result<Vec<OutputSnapshot>, GenerateError> {
+    let session = WaylandSession::connect()?;
+    session.manager.stop();
+    build_output_snapshots(&session.state)
+}
+
+/// Applies `profile` to the running compositor via `zwlr_output_configuration_v1`.
+///
+/// Connected outputs that the profile does not mention keep their current state.
+pub fn apply_profile_wayland(profile: &ProfileSettings) -> Result<(), GenerateError> {
+    let mut session = WaylandSession::connect()?;
+    let result = session.configure(profile, ConfigurationMode::Apply);
+    session.manager.stop();
+    result
+}
+
+/// Asks the compositor whether it would accept `profile`, without applying it.
+pub fn test_profile_wayland(profile: &ProfileSettings) -> Result<(), GenerateError> {
+    let mut session = WaylandSession::connect()?;
+    let result = session.verify(profile);
+    session.manager.stop();
+    result
+}
+
+/// Keeps the output-management session open and calls `on_change` with the
+/// current outputs after every `done` event, starting with the initial state.
+///
+/// Returns once the compositor finishes the output manager.
+pub fn watch_outputs_wayland(
+    mut on_change: impl FnMut(&[OutputSnapshot]),
+) -> Result<(), GenerateError> {
+    let mut session = WaylandSession::connect()?;
+    loop {
+        if std::mem::take(&mut session.state.done_received) {
+            let outputs = build_output_snapshots(&session.state)?;
+            on_change(&outputs);
+        }
+        if session.state.finished {
+            return Ok(());
+        }
+
+        session
+            .event_queue
+            .blocking_dispatch(&mut session.state)
+            .map_err(|source| GenerateError::WaylandProtocolError {
+                details: source.to_string(),
+            })?;
+    }
+}
 
+/// Decides which regenerated profiles a long-running watch writes.
+///
+/// The profile belongs to the set of outputs connected when the watch
+/// started; updates for any other set (e.g. after undocking) are refused so
+/// they cannot overwrite it with a different layout.
+#[derive(Debug, Default)]
+pub struct ProfileWatcher {
+    head_set: Option<Vec<(String, String)>>,
+    last_profile: Option<String>,
+}
+
+impl ProfileWatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `outputs` are the outputs the watched profile describes. The
+    /// first call decides which outputs that is.
+    pub fn is_watched_head_set(&mut self, outputs: &[OutputSnapshot]) -> bool {
+        let mut head_set = outputs
+            .iter()
+            .map(|output| (output.name.clone(), output.padded_identifier()))
+            .collect::<Vec<_>>();
+        head_set.sort();
+        match &self.head_set {
+            Some(watched) => *watched == head_set,
+            None => {
+                self.head_set = Some(head_set);
+                true
+            }
+        }
+    }
+
+    /// Whether `profile` differs from the last one that was written.
+    pub fn is_new_profile(&self, profile: &str) -> bool {
+        self.last_profile.as_deref() != Some(profile)
+    }
+
+    pub fn profile_written(&mut self, profile: String) {
+        self.last_profile = Some(profile);
+    }
+}
+
+/// Parses captured output state, detecting the JSON schema automatically.
 pub fn collect_outputs_from_json(raw_json: &[u8]) -> Result<Vec<OutputSnapshot>, GenerateError> {
-    let status: WlrStatus = serde_json::from_slice(raw_json).map_err(GenerateError::ParseJson)?;
-    Ok(status.0)
+    let value: serde_json::Value =
+        serde_json::from_slice(raw_json).map_err(GenerateError::ParseJson)?;
+    let format = InputFormat::detect(&value);
+    outputs_from_json_value(value, format)
+}
+
+pub fn collect_outputs_from_json_as(
+    raw_json: &[u8],
+    format: InputFormat,
+) -> Result<Vec<OutputSnapshot>, GenerateError> {
+    let value: serde_json::Value =
+        serde_json::from_slice(raw_json).map_err(GenerateError::ParseJson)?;
+    outputs_from_json_value(value, format)
+}
+
+fn outputs_from_json_value(
+    value: serde_json::Value,
+    format: InputFormat,
+) -> Result<Vec<OutputSnapshot>, GenerateError> {
+    match format {
+        InputFormat::Wlr => {
+            let status: WlrStatus =
+                serde_json::from_value(value).map_err(GenerateError::ParseJson)?;
+            Ok(status.0)
+        }
+        InputFormat::Sway => {
+            let outputs: Vec<SwayOutput> =
+                serde_json::from_value(value).map_err(GenerateError::ParseJson)?;
+            Ok(outputs.into_iter().map(OutputSnapshot::from).collect())
+        }
+        InputFormat::Hyprland => {
+            let monitors: Vec<HyprlandMonitor> =
+                serde_json::from_value(value).map_err(GenerateError::ParseJson)?;
+            Ok(monitors.into_iter().map(OutputSnapshot::from).collect())
+        }
+    }
+}
+
+/// Serializes `outputs` in the schema [`collect_outputs_from_json`] reads by
+/// default, so captures can be replayed with `--input-json`.
+pub fn dump_outputs_to_json(outputs: &[OutputSnapshot]) -> Result<String, GenerateError> {
+    let mut json = serde_json::to_string_pretty(outputs).map_err(GenerateError::SerializeJson)?;
+    json.push('\n');
+    Ok(json)
 }
 
 pub fn generate_profile_from_outputs(
     profile_name: &str,
     outputs: &[OutputSnapshot],
+) -> Result<String, GenerateError> {
+    render_outputs(OutputFormat::Kanshi, profile_name, outputs)
+}
+
+/// Renders `outputs` in the given configuration syntax. Formats without a
+/// notion of profiles only use `profile_name` for validation.
+pub fn render_outputs(
+    format: OutputFormat,
+    profile_name: &str,
+    outputs: &[OutputSnapshot],
+) -> Result<String, GenerateError> {
+    render_outputs_with_options(format, profile_name, outputs, &RenderOptions::default())
+}
+
+/// Like [`render_outputs`]; `options` only affect kanshi profiles, the other
+/// formats always use the identifiers and number formats their compositor
+/// expects.
+pub fn render_outputs_with_options(
+    format: OutputFormat,
+    profile_name: &str,
+    outputs: &[OutputSnapshot],
+    options: &RenderOptions,
 ) -> Result<String, GenerateError> {
     if profile_name.trim().is_empty() {
         return Err(GenerateError::EmptyProfileName);
     }
 
-    render_profile(profile_name, outputs)
+    match format {
+        OutputFormat::Kanshi => render_profile(profile_name, outputs, options),
+        OutputFormat::Sway => render_sway(outputs),
+        OutputFormat::Hyprland => render_hyprland(outputs),
+        OutputFormat::Niri => render_niri(outputs),
+        OutputFormat::WlrRandr => render_wlr_randr(outputs),
+    }
+}
+
+/// Keeps the outputs named in `only` (all outputs if it is empty) and drops
+/// those named in `exclude`. Outputs are named by connector or identifier;
+/// every name must match a connected output, and at least one output must
+/// remain.
+pub fn filter_outputs(
+    outputs: &[OutputSnapshot],
+    only: &[String],
+    exclude: &[String],
+) -> Result<Vec<OutputSnapshot>, GenerateError> {
+    if let Some(unknown) = only
+        .iter()
+        .chain(exclude)
+        .find(|selector| !outputs.iter().any(|output| output.is_selected_by(selector)))
+    {
+        return Err(GenerateError::UnknownOutput {
+            output: unknown.clone(),
+        });
+    }
+
+    let selected = outputs
+        .iter()
+        .filter(|output| {
+            only.is_empty() || only.iter().any(|selector| output.is_selected_by(selector))
+        })
+        .filter(|output| {
+            !exclude
+                .iter()
+                .any(|selector| output.is_selected_by(selector))
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    if selected.is_empty() && !(only.is_empty() && exclude.is_empty()) {
+        return Err(GenerateError::NoOutputsSelected);
+    }
+    Ok(selected)
 }
 
 pub fn generate_profile_from_slice(
@@ -417,31 +1580,16 @@ fn upsert_profile_in_config_with_outcome(
         return Err(GenerateError::EmptyProfileName);
     }
 
-    let blocks = parse_profile_blocks(config)?;
-    let mut matches = blocks
-        .iter()
-        .filter(|block| block.name == profile_name)
-        .collect::<Vec<_>>();
-
-    if matches.len() > 1 {
-        return Err(GenerateError::DuplicateProfileName {
-            profile_name: profile_name.to_owned(),
-            count: matches.len(),
-        });
-    }
+    let parsed = KanshiConfig::parse(config)?;
+    let existing = parsed.profile(profile_name)?;
 
     let mut canonical_block = new_profile_block.to_owned();
     if !canonical_block.ends_with('\n') {
         canonical_block.push('\n');
     }
 
-    let (mut merged, outcome) = if matches.is_empty() {
-        (
-            append_profile(config, &canonical_block),
-            UpsertOutcome::AppendedNew,
-        )
-    } else {
-        let target = matches.remove(0);
+    let (mut merged, outcome) = if let Some(existing) = existing {
+        let target = existing.span();
         let suffix = &config[target.end..];
         let replacement = if suffix.starts_with('\n') && canonical_block.ends_with('\n') {
             canonical_block
@@ -455,6 +1603,11 @@ fn upsert_profile_in_config_with_outcome(
         out.push_str(replacement);
         out.push_str(suffix);
         (out, UpsertOutcome::ReplacedExisting)
+    } else {
+        (
+            append_profile(config, &canonical_block),
+            UpsertOutcome::AppendedNew,
+        )
     };
 
     if !merged.ends_with('\n') {
@@ -469,27 +1622,23 @@ pub fn upsert_profile_in_file_with_outcome(
     profile_name: &str,
     new_profile_block: &str,
 ) -> Result<UpsertOutcome, GenerateError> {
-    let target_path = if config_path.exists() {
-        fs::canonicalize(config_path).unwrap_or_else(|_| config_path.to_path_buf())
-    } else {
-        config_path.to_path_buf()
-    };
-
-    let existing = match fs::read_to_string(&target_path) {
-        Ok(content) => content,
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
-        Err(source) => {
-            return Err(GenerateError::ConfigRead {
-                path: target_path.display().to_string(),
-                source,
-            });
-        }
-    };
+    upsert_profile_in_file_with_options(
+        config_path,
+        profile_name,
+        new_profile_block,
+        &ConfigWriteOptions::default(),
+    )
+}
 
-    let (merged, outcome) =
-        upsert_profile_in_config_with_outcome(&existing, profile_name, new_profile_block)?;
-    write_atomic(&target_path, &merged)?;
-    Ok(outcome)
+pub fn upsert_profile_in_file_with_options(
+    config_path: &Path,
+    profile_name: &str,
+    new_profile_block: &str,
+    options: &ConfigWriteOptions,
+) -> Result<UpsertOutcome, GenerateError> {
+    edit_config_file(config_path, options, |existing| {
+        upsert_profile_in_config_with_outcome(existing, profile_name, new_profile_block)
+    })
 }
 
 pub fn upsert_profile_in_file(
@@ -500,485 +1649,1928 @@ pub fn upsert_profile_in_file(
     upsert_profile_in_file_with_outcome(config_path, profile_name, new_profile_block).map(|_| ())
 }
 
-fn map_bind_error(error: BindError) -> GenerateError {
-    match error {
-        BindError::NotPresent | BindError::UnsupportedVersion => {
-            GenerateError::WaylandProtocolUnsupported
-        }
-    }
+/// Computes what [`upsert_profile_in_file_with_outcome`] would write without
+/// touching the file.
+pub fn preview_upsert_profile_in_file(
+    config_path: &Path,
+    profile_name: &str,
+    new_profile_block: &str,
+) -> Result<ConfigPreview, GenerateError> {
+    let target_path = config_target_path(config_path);
+    let current = read_config_file(&target_path)?;
+    let (updated, outcome) =
+        upsert_profile_in_config_with_outcome(&current, profile_name, new_profile_block)?;
+    Ok(ConfigPreview {
+        path: target_path,
+        current,
+        updated,
+        outcome,
+    })
 }
 
-fn build_output_snapshots(state: &WaylandState) -> Result<Vec<OutputSnapshot>, GenerateError> {
-    let mut outputs = Vec::with_capacity(state.heads.len());
+impl ConfigPreview {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 
-    for head_state in state.heads.values() {
-        let output_name = head_state
-            .name
-            .clone()
-            .unwrap_or_else(|| String::from("<unknown>"));
+    pub fn current(&self) -> &str {
+        &self.current
+    }
 
-        let mut modes = Vec::new();
-        for mode_id in &head_state.mode_ids {
-            let Some(mode_state) = state.modes.get(mode_id) else {
-                continue;
-            };
+    pub fn updated(&self) -> &str {
+        &self.updated
+    }
 
-            let width = mode_state
-                .width
-                .ok_or_else(|| GenerateError::WaylandProtocolError {
-                    details: format!("mode for output `{output_name}` missing width"),
-                })
-                .and_then(|value| {
-                    u32::try_from(value).map_err(|_| GenerateError::WaylandProtocolError {
-                        details: format!("mode for output `{output_name}` has negative width"),
-                    })
-                })?;
-            let height = mode_state
-                .height
-                .ok_or_else(|| GenerateError::WaylandProtocolError {
-                    details: format!("mode for output `{output_name}` missing height"),
-                })
-                .and_then(|value| {
-                    u32::try_from(value).map_err(|_| GenerateError::WaylandProtocolError {
-                        details: format!("mode for output `{output_name}` has negative height"),
-                    })
-                })?;
-            let refresh =
-                mode_state
-                    .refresh_mhz
-                    .ok_or_else(|| GenerateError::WaylandProtocolError {
-                        details: format!("mode for output `{output_name}` missing refresh rate"),
-                    })?;
+    pub fn outcome(&self) -> UpsertOutcome {
+        self.outcome
+    }
 
-            modes.push(ModeSnapshot {
-                width,
-                height,
-                refresh: f64::from(refresh) / 1000.0,
-                preferred: mode_state.preferred,
-                current: head_state.current_mode.as_ref() == Some(mode_id),
-            });
+    pub fn is_unchanged(&self) -> bool {
+        self.current == self.updated
+    }
+
+    /// Unified diff from the current to the updated config, empty if nothing changes.
+    pub fn unified_diff(&self) -> String {
+        if self.is_unchanged() {
+            return String::new();
         }
+        let path = self.path.display().to_string();
+        TextDiff::from_lines(&self.current, &self.updated)
+            .unified_diff()
+            .context_radius(3)
+            .header(&path, &path)
+            .to_string()
+    }
+}
 
-        let output = OutputSnapshot {
-            name: output_name,
-            make: head_state.make.clone().unwrap_or_default(),
-            model: head_state.model.clone().unwrap_or_default(),
-            serial: head_state.serial.clone(),
-            enabled: head_state.enabled.unwrap_or(false),
-            modes,
-            position: head_state.position.clone(),
-            scale: head_state.scale,
-            transform: head_state.transform.clone(),
-        };
+/// Names of all named profiles in `config`, in file order.
+pub fn list_profiles_in_config(config: &str) -> Result<Vec<String>, GenerateError> {
+    Ok(KanshiConfig::parse(config)?
+        .profiles()
+        .filter_map(ProfileBlock::name)
+        .map(str::to_owned)
+        .collect())
+}
 
-        outputs.push(output);
-    }
+/// The verbatim text of profile `profile_name`, from `profile` to the closing brace.
+pub fn show_profile_in_config(config: &str, profile_name: &str) -> Result<String, GenerateError> {
+    let parsed = KanshiConfig::parse(config)?;
+    let profile = parsed.require_profile(profile_name)?;
+    Ok(parsed.text(profile.span()).to_owned())
+}
 
-    outputs.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
+pub fn remove_profile_from_config(
+    config: &str,
+    profile_name: &str,
+) -> Result<String, GenerateError> {
+    let parsed = KanshiConfig::parse(config)?;
+    let target = parsed.require_profile(profile_name)?.span();
+
+    // Take the rest of the closing line, including a trailing comment, with
+    // the block, then drop one blank line if the block was separated from its
+    // neighbours on both sides.
+    let mut end = target.end;
+    let rest = &config[end..];
+    let line_end = rest.find('\n').map_or(rest.len(), |offset| offset + 1);
+    let closing_line = rest[..line_end].trim();
+    if closing_line.is_empty() || closing_line.starts_with('#') {
+        end += line_end;
+    }
+    let prefix = &config[..target.start];
+    let mut suffix = &config[end..];
+    if (prefix.is_empty() || prefix.ends_with("\n\n")) && suffix.starts_with('\n') {
+        suffix = &suffix[1..];
+    }
 
-    Ok(outputs)
+    let mut out = String::with_capacity(config.len());
+    out.push_str(prefix);
+    out.push_str(suffix);
+    if out.trim().is_empty() {
+        out.clear();
+    } else if out.ends_with("\n\n") {
+        out.truncate(out.trim_end_matches('\n').len() + 1);
+    }
+    Ok(out)
 }
 
-fn write_atomic(path: &Path, content: &str) -> Result<(), GenerateError> {
-    let parent = path.parent().ok_or_else(|| GenerateError::ConfigWrite {
-        path: path.display().to_string(),
-        source: std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "config path has no parent directory",
-        ),
-    })?;
+pub fn rename_profile_in_config(
+    config: &str,
+    profile_name: &str,
+    new_profile_name: &str,
+) -> Result<String, GenerateError> {
+    if new_profile_name.trim().is_empty() {
+        return Err(GenerateError::EmptyProfileName);
+    }
 
-    fs::create_dir_all(parent).map_err(|source| GenerateError::ConfigWrite {
-        path: parent.display().to_string(),
-        source,
-    })?;
+    let parsed = KanshiConfig::parse(config)?;
+    let profile = parsed.require_profile(profile_name)?;
+    if profile_name != new_profile_name && parsed.profile(new_profile_name)?.is_some() {
+        return Err(GenerateError::ProfileAlreadyExists {
+            profile_name: new_profile_name.to_owned(),
+        });
+    }
+    let target = profile
+        .name
+        .as_ref()
+        .expect("profiles found by name have a name")
+        .span();
+
+    let mut out = String::with_capacity(config.len() + new_profile_name.len());
+    out.push_str(&config[..target.start]);
+    out.push_str(&profile_name_token(new_profile_name));
+    out.push_str(&config[target.end..]);
+    Ok(out)
+}
 
-    let file_name = path
-        .file_name()
-        .and_then(std::ffi::OsStr::to_str)
-        .unwrap_or("config");
+pub fn list_profiles_in_file(config_path: &Path) -> Result<Vec<String>, GenerateError> {
+    list_profiles_in_config(&read_config_file(config_path)?)
+}
 
-    let mut temp_path = None;
-    let mut temp_file = None;
-    for attempt in 0..64_u32 {
-        let candidate = parent.join(format!(
-            ".{file_name}.kanshi-generate.{}.{}.tmp",
-            std::process::id(),
-            attempt
-        ));
-
-        match OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .open(&candidate)
-        {
-            Ok(file) => {
-                temp_path = Some(candidate);
-                temp_file = Some(file);
-                break;
-            }
-            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
-            Err(source) => {
-                return Err(GenerateError::ConfigWrite {
-                    path: candidate.display().to_string(),
-                    source,
-                });
-            }
-        }
-    }
-
-    let temp_path = temp_path.ok_or_else(|| GenerateError::ConfigWrite {
-        path: path.display().to_string(),
-        source: std::io::Error::new(
-            std::io::ErrorKind::AlreadyExists,
-            "failed to allocate unique temporary file",
-        ),
-    })?;
-
-    let mut temp_file = temp_file.expect("temp file must exist when temp path exists");
-
-    if let Err(source) = temp_file.write_all(content.as_bytes()) {
-        let _ = fs::remove_file(&temp_path);
-        return Err(GenerateError::ConfigWrite {
-            path: temp_path.display().to_string(),
-            source,
-        });
-    }
-
-    if let Err(source) = temp_file.sync_all() {
-        let _ = fs::remove_file(&temp_path);
-        return Err(GenerateError::ConfigWrite {
-            path: temp_path.display().to_string(),
-            source,
-        });
-    }
-
-    drop(temp_file);
+pub fn show_profile_in_file(
+    config_path: &Path,
+    profile_name: &str,
+) -> Result<String, GenerateError> {
+    show_profile_in_config(&read_config_file(config_path)?, profile_name)
+}
 
-    fs::rename(&temp_path, path).map_err(|source| {
-        let _ = fs::remove_file(&temp_path);
-        GenerateError::ConfigWrite {
-            path: path.display().to_string(),
-            source,
-        }
+pub fn remove_profile_from_file(
+    config_path: &Path,
+    profile_name: &str,
+    options: &ConfigWriteOptions,
+) -> Result<(), GenerateError> {
+    edit_config_file(config_path, options, |existing| {
+        Ok((remove_profile_from_config(existing, profile_name)?, ()))
     })
 }
 
-fn append_profile(config: &str, profile_block: &str) -> String {
-    if config.is_empty() {
-        return profile_block.to_owned();
-    }
-
-    let mut out = String::with_capacity(config.len() + profile_block.len() + 2);
-    out.push_str(config);
+pub fn rename_profile_in_file(
+    config_path: &Path,
+    profile_name: &str,
+    new_profile_name: &str,
+    options: &ConfigWriteOptions,
+) -> Result<(), GenerateError> {
+    edit_config_file(config_path, options, |existing| {
+        Ok((
+            rename_profile_in_config(existing, profile_name, new_profile_name)?,
+            (),
+        ))
+    })
+}
 
-    if out.ends_with("\n\n") {
-        // exactly one blank separator already present
-    } else if out.ends_with('\n') {
-        out.push('\n');
+fn config_target_path(config_path: &Path) -> PathBuf {
+    if config_path.exists() {
+        fs::canonicalize(config_path).unwrap_or_else(|_| config_path.to_path_buf())
     } else {
-        out.push_str("\n\n");
+        config_path.to_path_buf()
     }
+}
 
-    out.push_str(profile_block);
-    out
+/// Reads the config, treating a missing file as empty.
+fn read_config_file(config_path: &Path) -> Result<String, GenerateError> {
+    read_config_file_with_fingerprint(&config_target_path(config_path)).map(|(content, _)| content)
 }
 
-fn parse_profile_blocks(config: &str) -> Result<Vec<ProfileBlock>, GenerateError> {
-    let bytes = config.as_bytes();
-    let mut blocks = Vec::new();
-    let mut i = 0;
-    let mut in_comment = false;
-    let mut in_string = false;
-    let mut escaped = false;
+fn read_config_file_with_fingerprint(
+    target_path: &Path,
+) -> Result<(String, ConfigFingerprint), GenerateError> {
+    let read_error = |source| GenerateError::ConfigRead {
+        path: target_path.display().to_string(),
+        source,
+    };
 
-    while i < bytes.len() {
-        let ch = bytes[i];
+    // Metadata is taken before the content so that a write in between shows
+    // up as a mismatch later rather than going unnoticed.
+    let metadata = match fs::metadata(target_path) {
+        Ok(metadata) => Some((
+            file_identity(&metadata),
+            metadata.len(),
+            metadata.modified().ok(),
+        )),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(source) => return Err(read_error(source)),
+    };
+    let content = match fs::read_to_string(target_path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(source) => return Err(read_error(source)),
+    };
 
-        if in_comment {
-            if ch == b'\n' {
-                in_comment = false;
-            }
-            i += 1;
-            continue;
-        }
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    let fingerprint = ConfigFingerprint {
+        metadata,
+        content_hash: hasher.finish(),
+    };
+    Ok((content, fingerprint))
+}
 
-        if in_string {
-            if escaped {
-                escaped = false;
-            } else if ch == b'\\' {
-                escaped = true;
-            } else if ch == b'"' {
-                in_string = false;
-            }
-            i += 1;
-            continue;
-        }
+/// Backups of the config at `config_path`, newest first.
+pub fn list_config_backups(config_path: &Path) -> Result<Vec<ConfigBackup>, GenerateError> {
+    let target_path = config_target_path(config_path);
+    let Some((directory, prefix)) = backup_location(&target_path) else {
+        return Ok(Vec::new());
+    };
 
-        match ch {
-            b'#' => {
-                in_comment = true;
-                i += 1;
-                continue;
-            }
-            b'"' => {
-                in_string = true;
-                i += 1;
-                continue;
-            }
-            b'p' if is_profile_start(bytes, i) => {
-                let (block, next_index) = parse_profile_block(config, i)?;
-                blocks.push(block);
-                i = next_index;
-                continue;
-            }
-            _ => {
-                i += 1;
-            }
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(source) => {
+            return Err(GenerateError::ConfigRead {
+                path: directory.display().to_string(),
+                source,
+            });
         }
-    }
+    };
 
-    Ok(blocks)
+    let mut backups = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            let timestamp = file_name.strip_prefix(&prefix)?.to_owned();
+            Some(ConfigBackup {
+                path: entry.path(),
+                timestamp,
+            })
+        })
+        .collect::<Vec<_>>();
+    backups.sort_by(|left, right| right.sort_key().cmp(&left.sort_key()));
+    Ok(backups)
 }
 
-fn is_profile_start(bytes: &[u8], index: usize) -> bool {
-    let token_end = index + PROFILE_KEYWORD.len();
-    if token_end > bytes.len() {
-        return false;
-    }
-
-    if &bytes[index..token_end] != PROFILE_KEYWORD {
-        return false;
-    }
-
-    let before_ok = index == 0 || !is_identifier_char(bytes[index - 1]);
-    let after_ok = token_end < bytes.len() && bytes[token_end].is_ascii_whitespace();
-    before_ok && after_ok
+/// Replaces the config with the content of `backup`, backing up the current
+/// config first so the restore can itself be undone.
+pub fn restore_config_backup(
+    config_path: &Path,
+    backup: &ConfigBackup,
+    options: &ConfigWriteOptions,
+) -> Result<(), GenerateError> {
+    let content = fs::read_to_string(&backup.path).map_err(|source| GenerateError::ConfigRead {
+        path: backup.path.display().to_string(),
+        source,
+    })?;
+    edit_config_file(config_path, options, |_| Ok((content.clone(), ())))
 }
 
-fn parse_profile_block(config: &str, start: usize) -> Result<(ProfileBlock, usize), GenerateError> {
-    let bytes = config.as_bytes();
-    let token_end = start + PROFILE_KEYWORD.len();
-
-    let mut i = token_end;
-    while i < bytes.len() && bytes[i].is_ascii_whitespace() {
-        i += 1;
+impl ConfigBackup {
+    pub fn path(&self) -> &Path {
+        &self.path
     }
 
-    let name_start = i;
-    let mut in_string = false;
-    let mut in_comment = false;
-    let mut escaped = false;
-
-    while i < bytes.len() {
-        let ch = bytes[i];
-
-        if in_comment {
-            if ch == b'\n' {
-                in_comment = false;
-            }
-            i += 1;
-            continue;
-        }
+    /// UTC creation time as `YYYYMMDDTHHMMSS.mmmZ`, possibly followed by a
+    /// `-N` suffix when several backups were taken within one millisecond.
+    pub fn timestamp(&self) -> &str {
+        &self.timestamp
+    }
 
-        if in_string {
-            if escaped {
-                escaped = false;
-            } else if ch == b'\\' {
-                escaped = true;
-            } else if ch == b'"' {
-                in_string = false;
-            }
-            i += 1;
-            continue;
+    /// Orders backups by time and then numerically by suffix, so `-10` comes
+    /// after `-2`.
+    fn sort_key(&self) -> (&str, u32) {
+        match self.timestamp.split_once('-') {
+            Some((time, suffix)) => (time, suffix.parse().unwrap_or(0)),
+            None => (&self.timestamp, 0),
         }
+    }
+}
 
-        match ch {
-            b'#' => {
-                in_comment = true;
-                i += 1;
+/// Reads the config, lets `edit` rewrite it and atomically replaces the file
+/// with the result. Nothing is written if `edit` fails or if the file changes
+/// before it is replaced; in the latter case `edit` is run again on the new
+/// content up to `options.modification_retries` times.
+fn edit_config_file<T>(
+    config_path: &Path,
+    options: &ConfigWriteOptions,
+    mut edit: impl FnMut(&str) -> Result<(String, T), GenerateError>,
+) -> Result<T, GenerateError> {
+    let target_path = config_target_path(config_path);
+    let _lock = lock_config(&target_path, options.lock_timeout)?;
+
+    let mut attempt = 0;
+    loop {
+        let (existing, fingerprint) = read_config_file_with_fingerprint(&target_path)?;
+        let (updated, outcome) = edit(&existing)?;
+        let backup = || {
+            if existing.is_empty() || updated == existing {
+                return Ok(());
             }
-            b'"' => {
-                in_string = true;
-                i += 1;
+            backup_config(&target_path, &existing, options.backup_count)
+        };
+        match write_atomic(&target_path, &updated, &fingerprint, backup) {
+            Err(GenerateError::ConfigModifiedConcurrently { .. })
+                if attempt < options.modification_retries =>
+            {
+                attempt += 1;
             }
-            b'{' => break,
-            _ => i += 1,
+            result => return result.map(|()| outcome),
         }
     }
+}
 
-    if i >= bytes.len() || bytes[i] != b'{' {
-        return Err(GenerateError::ConfigParse {
-            details: format!("profile block starting at byte {start} has no opening brace"),
-        });
-    }
-
-    let name = config[name_start..i].trim().to_owned();
-    if name.is_empty() {
-        return Err(GenerateError::ConfigParse {
-            details: format!("profile block starting at byte {start} has an empty profile name"),
-        });
+/// Takes an exclusive `flock` on a lock file next to the config, polling
+/// until `timeout` elapses. The lock is released when the returned file is
+/// dropped; the lock file itself is left in place so every process locks the
+/// same inode.
+fn lock_config(target_path: &Path, timeout: Duration) -> Result<File, GenerateError> {
+    let lock_path = config_lock_path(target_path);
+    if let Some(parent) = lock_path.parent() {
+        fs::create_dir_all(parent).map_err(|source| GenerateError::ConfigWrite {
+            path: parent.display().to_string(),
+            source,
+        })?;
     }
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .map_err(|source| GenerateError::ConfigWrite {
+            path: lock_path.display().to_string(),
+            source,
+        })?;
 
-    let mut depth = 1usize;
-    let mut j = i + 1;
-    in_string = false;
-    in_comment = false;
-    escaped = false;
-
-    while j < bytes.len() {
-        let ch = bytes[j];
-
-        if in_comment {
-            if ch == b'\n' {
-                in_comment = false;
-            }
-            j += 1;
-            continue;
-        }
-
-        if in_string {
-            if escaped {
-                escaped = false;
-            } else if ch == b'\\' {
-                escaped = true;
-            } else if ch == b'"' {
-                in_string = false;
+    let deadline = Instant::now() + timeout;
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(file),
+            Err(TryLockError::WouldBlock) if Instant::now() < deadline => {
+                std::thread::sleep(CONFIG_LOCK_POLL_INTERVAL);
             }
-            j += 1;
-            continue;
-        }
-
-        match ch {
-            b'#' => {
-                in_comment = true;
-                j += 1;
-            }
-            b'"' => {
-                in_string = true;
-                j += 1;
-            }
-            b'{' => {
-                depth += 1;
-                j += 1;
-            }
-            b'}' => {
-                depth -= 1;
-                j += 1;
-                if depth == 0 {
-                    return Ok((
-                        ProfileBlock {
-                            name,
-                            start,
-                            end: j,
-                        },
-                        j,
-                    ));
-                }
+            Err(TryLockError::WouldBlock) => {
+                return Err(GenerateError::ConfigLocked {
+                    path: target_path.display().to_string(),
+                    timeout,
+                });
             }
-            _ => {
-                j += 1;
+            Err(TryLockError::Error(source)) => {
+                return Err(GenerateError::ConfigWrite {
+                    path: lock_path.display().to_string(),
+                    source,
+                });
             }
         }
     }
+}
 
-    Err(GenerateError::ConfigParse {
-        details: format!("profile `{name}` has an unclosed block"),
-    })
+fn config_lock_path(target_path: &Path) -> PathBuf {
+    let file_name = target_path
+        .file_name()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or("config");
+    target_path.with_file_name(format!(".{file_name}.kanshi-generate.lock"))
 }
 
-fn is_identifier_char(ch: u8) -> bool {
-    ch.is_ascii_alphanumeric() || ch == b'_' || ch == b'-'
+/// Directory and file-name prefix shared by all backups of `target_path`.
+fn backup_location(target_path: &Path) -> Option<(&Path, String)> {
+    let directory = target_path.parent()?;
+    let file_name = target_path.file_name()?.to_str()?;
+    Some((directory, format!("{file_name}.kanshi-generate.bak.")))
 }
 
-fn render_profile(profile_name: &str, outputs: &[OutputSnapshot]) -> Result<String, GenerateError> {
-    let mut profile = String::with_capacity(32 + outputs.len() * 128);
-    writeln!(&mut profile, "profile {profile_name} {{").map_err(|_| GenerateError::Format)?;
+/// Writes `content` to a new timestamped backup next to `target_path` and
+/// prunes all but the newest `backup_count` backups.
+fn backup_config(
+    target_path: &Path,
+    content: &str,
+    backup_count: usize,
+) -> Result<(), GenerateError> {
+    if backup_count == 0 {
+        return Ok(());
+    }
+    let Some((directory, prefix)) = backup_location(target_path) else {
+        return Ok(());
+    };
 
-    for output in outputs {
-        let output_id = escape_kanshi_quoted(&output.identifier());
-        if output.enabled {
-            let mode = output
-                .active_mode()
-                .ok_or_else(|| GenerateError::MissingMode {
-                    output: output.name.clone(),
-                })?;
-            let position =
-                output
-                    .position
-                    .as_ref()
-                    .ok_or_else(|| GenerateError::MissingPosition {
-                        output: output.name.clone(),
-                    })?;
-            let scale = output.scale.ok_or_else(|| GenerateError::MissingScale {
-                output: output.name.clone(),
-            })?;
-            if let Some(transform) = output.normalized_transform() {
-                writeln!(
-                    &mut profile,
-                    "  output \"{output_id}\" mode {}x{}@{:.2}Hz position {},{} scale {:.2} transform {transform}",
-                    mode.width, mode.height, mode.refresh, position.x, position.y, scale
-                )
-                .map_err(|_| GenerateError::Format)?;
-            } else {
-                writeln!(
-                    &mut profile,
-                    "  output \"{output_id}\" mode {}x{}@{:.2}Hz position {},{} scale {:.2}",
-                    mode.width, mode.height, mode.refresh, position.x, position.y, scale
-                )
-                .map_err(|_| GenerateError::Format)?;
-            }
+    let timestamp = backup_timestamp(std::time::SystemTime::now());
+    let permissions = fs::metadata(target_path)
+        .ok()
+        .map(|metadata| metadata.permissions());
+    let mut attempt = 0_u32;
+    let (backup_path, mut file) = loop {
+        let suffix = if attempt == 0 {
+            String::new()
         } else {
-            writeln!(&mut profile, "  output \"{output_id}\" disable")
-                .map_err(|_| GenerateError::Format)?;
+            format!("-{attempt}")
+        };
+        let candidate = directory.join(format!("{prefix}{timestamp}{suffix}"));
+        match OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&candidate)
+        {
+            Ok(file) => break (candidate, file),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists && attempt < 64 => {
+                attempt += 1;
+            }
+            Err(source) => {
+                return Err(GenerateError::ConfigBackup {
+                    path: candidate.display().to_string(),
+                    source,
+                });
+            }
         }
+    };
+
+    let written = permissions
+        .map_or(Ok(()), |permissions| file.set_permissions(permissions))
+        .and_then(|()| file.write_all(content.as_bytes()))
+        .and_then(|()| file.sync_all());
+    if let Err(source) = written {
+        let _ = fs::remove_file(&backup_path);
+        return Err(GenerateError::ConfigBackup {
+            path: backup_path.display().to_string(),
+            source,
+        });
     }
 
-    profile.push_str("}\n");
-    Ok(profile)
+    for stale in list_config_backups(target_path)?
+        .into_iter()
+        .skip(backup_count)
+    {
+        fs::remove_file(&stale.path).map_err(|source| GenerateError::ConfigBackup {
+            path: stale.path.display().to_string(),
+            source,
+        })?;
+    }
+    Ok(())
 }
 
-fn escape_kanshi_quoted(raw: &str) -> String {
-    let mut escaped = String::with_capacity(raw.len());
-    for ch in raw.chars() {
-        match ch {
-            '\\' => escaped.push_str("\\\\"),
-            '"' => escaped.push_str("\\\""),
-            _ => escaped.push(ch),
-        }
-    }
-    escaped
+/// Formats `time` as a sortable UTC timestamp (`YYYYMMDDTHHMMSS.mmmZ`).
+fn backup_timestamp(time: std::time::SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (days, seconds_of_day) = (seconds / 86_400, seconds % 86_400);
+
+    // Civil-from-days (Howard Hinnant), shifted so the era starts in March.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}.{:03}Z",
+        seconds_of_day / 3_600,
+        seconds_of_day / 60 % 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
 }
 
-fn transform_from_wayland(
-    transform: wayland_client::WEnum<wl_output::Transform>,
-) -> Option<String> {
-    match transform {
-        wayland_client::WEnum::Value(wl_output::Transform::Normal) => Some(String::from("normal")),
-        wayland_client::WEnum::Value(wl_output::Transform::_90) => Some(String::from("90")),
-        wayland_client::WEnum::Value(wl_output::Transform::_180) => Some(String::from("180")),
-        wayland_client::WEnum::Value(wl_output::Transform::_270) => Some(String::from("270")),
-        wayland_client::WEnum::Value(wl_output::Transform::Flipped) => {
-            Some(String::from("flipped"))
-        }
-        wayland_client::WEnum::Value(wl_output::Transform::Flipped90) => {
-            Some(String::from("flipped-90"))
-        }
-        wayland_client::WEnum::Value(wl_output::Transform::Flipped180) => {
-            Some(String::from("flipped-180"))
-        }
-        wayland_client::WEnum::Value(wl_output::Transform::Flipped270) => {
-            Some(String::from("flipped-270"))
+fn map_bind_error(error: BindError) -> GenerateError {
+    match error {
+        BindError::NotPresent | BindError::UnsupportedVersion => {
+            GenerateError::WaylandProtocolUnsupported
         }
-        wayland_client::WEnum::Value(_) => None,
-        wayland_client::WEnum::Unknown(raw) => normalize_transform_u32(raw).map(String::from),
     }
 }
 
-fn normalize_transform_u32(raw: u32) -> Option<&'static str> {
-    match raw {
-        0 => Some("normal"),
-        1 => Some("90"),
+fn build_output_snapshots(state: &WaylandState) -> Result<Vec<OutputSnapshot>, GenerateError> {
+    Ok(build_head_snapshots(state)?
+        .into_iter()
+        .map(|(_, output)| output)
+        .collect())
+}
+
+fn build_head_snapshots(
+    state: &WaylandState,
+) -> Result<Vec<(ObjectId, OutputSnapshot)>, GenerateError> {
+    let mut outputs = Vec::with_capacity(state.heads.len());
+
+    for (head_id, head_state) in &state.heads {
+        let output_name = head_state
+            .name
+            .clone()
+            .unwrap_or_else(|| String::from("<unknown>"));
+
+        // Some compositors report a current mode the head never advertised.
+        let unlisted_current_mode = head_state
+            .current_mode
+            .as_ref()
+            .filter(|mode_id| !head_state.mode_ids.contains(mode_id));
+
+        let mut modes = Vec::new();
+        for mode_id in head_state.mode_ids.iter().chain(unlisted_current_mode) {
+            let Some(mode_state) = state.modes.get(mode_id) else {
+                continue;
+            };
+
+            let width = mode_state
+                .width
+                .ok_or_else(|| GenerateError::WaylandProtocolError {
+                    details: format!("mode for output `{output_name}` missing width"),
+                })
+                .and_then(|value| {
+                    u32::try_from(value).map_err(|_| GenerateError::WaylandProtocolError {
+                        details: format!("mode for output `{output_name}` has negative width"),
+                    })
+                })?;
+            let height = mode_state
+                .height
+                .ok_or_else(|| GenerateError::WaylandProtocolError {
+                    details: format!("mode for output `{output_name}` missing height"),
+                })
+                .and_then(|value| {
+                    u32::try_from(value).map_err(|_| GenerateError::WaylandProtocolError {
+                        details: format!("mode for output `{output_name}` has negative height"),
+                    })
+                })?;
+            let refresh =
+                mode_state
+                    .refresh_mhz
+                    .ok_or_else(|| GenerateError::WaylandProtocolError {
+                        details: format!("mode for output `{output_name}` missing refresh rate"),
+                    })?;
+
+            modes.push(ModeSnapshot {
+                width,
+                height,
+                refresh: f64::from(refresh) / 1000.0,
+                preferred: mode_state.preferred,
+                current: head_state.current_mode.as_ref() == Some(mode_id),
+                custom_mode: unlisted_current_mode == Some(mode_id),
+            });
+        }
+
+        let output = OutputSnapshot {
+            name: output_name,
+            make: head_state.make.clone().unwrap_or_default(),
+            model: head_state.model.clone().unwrap_or_default(),
+            serial: head_state.serial.clone(),
+            enabled: head_state.enabled.unwrap_or(false),
+            modes,
+            position: head_state.position.clone(),
+            scale: head_state.scale,
+            transform: head_state.transform.clone(),
+            adaptive_sync: head_state.adaptive_sync,
+            description: head_state.description.clone(),
+            physical_size: head_state.physical_size,
+        };
+
+        outputs.push((head_id.clone(), output));
+    }
+
+    outputs.sort_by(|(_, lhs), (_, rhs)| lhs.name.cmp(&rhs.name));
+
+    Ok(outputs)
+}
+
+fn set_custom_mode(
+    head_configuration: &ZwlrOutputConfigurationHeadV1,
+    mode: &ModeSetting,
+    output_name: &str,
+) -> Result<(), GenerateError> {
+    let (width, height) = mode.protocol_size(output_name)?;
+    head_configuration.set_custom_mode(width, height, mode.refresh.map_or(0, refresh_to_mhz));
+    Ok(())
+}
+
+/// Parses `WIDTHxHEIGHT[@REFRESH[Hz]]` as used by kanshi's `mode` directive.
+fn parse_mode_setting(raw: &str, custom: bool) -> Option<ModeSetting> {
+    let (size, refresh) = match raw.split_once('@') {
+        Some((size, refresh)) => {
+            let refresh = refresh.strip_suffix("Hz").unwrap_or(refresh);
+            (size, Some(refresh.parse::<f64>().ok()?))
+        }
+        None => (raw, None),
+    };
+    let (width, height) = size.split_once('x')?;
+    Some(ModeSetting {
+        width: width.parse().ok()?,
+        height: height.parse().ok()?,
+        refresh,
+        custom,
+    })
+}
+
+fn parse_position(raw: &str) -> Option<PositionSnapshot> {
+    let (x, y) = raw.split_once(',')?;
+    Some(PositionSnapshot {
+        x: x.trim().parse().ok()?,
+        y: y.trim().parse().ok()?,
+    })
+}
+
+fn refresh_to_mhz(refresh: f64) -> i32 {
+    (refresh * 1000.0).round() as i32
+}
+
+/// Resolves which profile output applies to each head the way kanshi does:
+/// exact criteria claim their head first, `*` then takes any remaining head.
+fn match_profile_to_heads(
+    heads: &[OutputSnapshot],
+    profile: &ProfileSettings,
+) -> Result<Vec<Option<OutputSettings>>, GenerateError> {
+    let mut assignments = vec![None; heads.len()];
+    let (wildcards, exact): (Vec<_>, Vec<_>) = profile
+        .outputs
+        .iter()
+        .partition(|settings| settings.criteria.contains('*'));
+    // Narrow patterns like `make model *` claim their heads before a bare `*`.
+    let (catch_all, patterns): (Vec<_>, Vec<_>) = wildcards
+        .into_iter()
+        .partition(|settings| settings.criteria == "*");
+
+    for settings in exact.into_iter().chain(patterns).chain(catch_all) {
+        let index = heads
+            .iter()
+            .enumerate()
+            .position(|(index, head)| {
+                assignments[index].is_none() && head.matches_criteria(&settings.criteria)
+            })
+            .ok_or_else(|| GenerateError::ProfileOutputNotConnected {
+                criteria: settings.criteria.clone(),
+            })?;
+        let defaults = profile
+            .defaults
+            .iter()
+            .find(|defaults| heads[index].matches_criteria(&defaults.criteria));
+        assignments[index] = Some(settings.merged_over(defaults));
+    }
+
+    Ok(assignments)
+}
+
+/// Replaces `path` with `content` through a synced temporary file, refusing
+/// to rename over it if it no longer matches `unchanged_since`. The lock only
+/// keeps other kanshi-generate processes out, so this catches editors saving
+/// in between; a write landing after the final check can still be lost.
+/// `before_replace` runs only once the file is known to be unchanged, so a
+/// refused write leaves no backup behind.
+fn write_atomic(
+    path: &Path,
+    content: &str,
+    unchanged_since: &ConfigFingerprint,
+    before_replace: impl FnOnce() -> Result<(), GenerateError>,
+) -> Result<(), GenerateError> {
+    let parent = path.parent().ok_or_else(|| GenerateError::ConfigWrite {
+        path: path.display().to_string(),
+        source: std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "config path has no parent directory",
+        ),
+    })?;
+
+    fs::create_dir_all(parent).map_err(|source| GenerateError::ConfigWrite {
+        path: parent.display().to_string(),
+        source,
+    })?;
+
+    let file_name = path
+        .file_name()
+        .and_then(std::ffi::OsStr::to_str)
+        .unwrap_or("config");
+
+    let mut temp_path = None;
+    let mut temp_file = None;
+    for attempt in 0..64_u32 {
+        let candidate = parent.join(format!(
+            ".{file_name}.kanshi-generate.{}.{}.tmp",
+            std::process::id(),
+            attempt
+        ));
+
+        match OpenOptions::new()
+            .create_new(true)
+            .write(true)
+            .open(&candidate)
+        {
+            Ok(file) => {
+                temp_path = Some(candidate);
+                temp_file = Some(file);
+                break;
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(source) => {
+                return Err(GenerateError::ConfigWrite {
+                    path: candidate.display().to_string(),
+                    source,
+                });
+            }
+        }
+    }
+
+    let temp_path = temp_path.ok_or_else(|| GenerateError::ConfigWrite {
+        path: path.display().to_string(),
+        source: std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "failed to allocate unique temporary file",
+        ),
+    })?;
+
+    let mut temp_file = temp_file.expect("temp file must exist when temp path exists");
+
+    if let Err(source) = copy_file_metadata(path, &temp_file) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(GenerateError::ConfigWrite {
+            path: temp_path.display().to_string(),
+            source,
+        });
+    }
+
+    if let Err(source) = temp_file.write_all(content.as_bytes()) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(GenerateError::ConfigWrite {
+            path: temp_path.display().to_string(),
+            source,
+        });
+    }
+
+    if let Err(source) = temp_file.sync_all() {
+        let _ = fs::remove_file(&temp_path);
+        return Err(GenerateError::ConfigWrite {
+            path: temp_path.display().to_string(),
+            source,
+        });
+    }
+
+    drop(temp_file);
+
+    let unchanged = read_config_file_with_fingerprint(path)
+        .map(|(_, fingerprint)| fingerprint == *unchanged_since);
+    if !matches!(unchanged, Ok(true)) {
+        let _ = fs::remove_file(&temp_path);
+        unchanged?;
+        return Err(GenerateError::ConfigModifiedConcurrently {
+            path: path.display().to_string(),
+        });
+    }
+
+    if let Err(err) = before_replace() {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+
+    fs::rename(&temp_path, path).map_err(|source| {
+        let _ = fs::remove_file(&temp_path);
+        GenerateError::ConfigWrite {
+            path: path.display().to_string(),
+            source,
+        }
+    })?;
+
+    // Make the rename itself durable, not just the file content.
+    File::open(parent)
+        .and_then(|directory| directory.sync_all())
+        .map_err(|source| GenerateError::ConfigWrite {
+            path: parent.display().to_string(),
+            source,
+        })
+}
+
+/// Device and inode, so a file replaced by a same-sized one within the mtime
+/// granularity is still noticed.
+#[cfg(unix)]
+fn file_identity(metadata: &fs::Metadata) -> FileIdentity {
+    use std::os::unix::fs::MetadataExt as _;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+/// Without device/inode numbers only length, mtime and content hash count.
+#[cfg(not(unix))]
+fn file_identity(_: &fs::Metadata) -> FileIdentity {
+    None
+}
+
+/// Gives `destination` the owner (when permitted), mode and extended
+/// attributes of the file at `source`, if it exists.
+fn copy_file_metadata(source: &Path, destination: &File) -> std::io::Result<()> {
+    let metadata = match fs::metadata(source) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    // Ownership first: chown may clear setuid/setgid bits set by the chmod.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt as _;
+        match std::os::unix::fs::fchown(destination, Some(metadata.uid()), Some(metadata.gid())) {
+            Err(err) if err.kind() != std::io::ErrorKind::PermissionDenied => return Err(err),
+            _ => {}
+        }
+    }
+    destination.set_permissions(metadata.permissions())?;
+
+    let names = match xattr::list(source) {
+        Ok(names) => names,
+        Err(err) if is_unsupported_or_denied(&err) => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    for name in names {
+        let Some(value) = xattr::get(source, &name)? else {
+            continue;
+        };
+        match destination.set_xattr(&name, &value) {
+            Err(err) if !is_unsupported_or_denied(&err) => return Err(err),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Errors from metadata calls that a filesystem or an unprivileged user
+/// cannot satisfy; copying such metadata is skipped.
+fn is_unsupported_or_denied(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::Unsupported | std::io::ErrorKind::PermissionDenied
+    )
+}
+
+fn append_profile(config: &str, profile_block: &str) -> String {
+    if config.is_empty() {
+        return profile_block.to_owned();
+    }
+
+    let mut out = String::with_capacity(config.len() + profile_block.len() + 2);
+    out.push_str(config);
+
+    if out.ends_with("\n\n") {
+        // exactly one blank separator already present
+    } else if out.ends_with('\n') {
+        out.push('\n');
+    } else {
+        out.push_str("\n\n");
+    }
+
+    out.push_str(profile_block);
+    out
+}
+
+impl KanshiConfig {
+    pub fn parse(source: &str) -> Result<Self, GenerateError> {
+        let items = ConfigParser::new(source).parse_config()?;
+        Ok(Self {
+            source: source.to_owned(),
+            items,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn text(&self, span: Span) -> &str {
+        &self.source[span.start..span.end]
+    }
+
+    pub fn items(&self) -> &[ConfigItem] {
+        &self.items
+    }
+
+    pub fn profiles(&self) -> impl Iterator<Item = &ProfileBlock> {
+        self.items.iter().filter_map(|item| match item {
+            ConfigItem::Profile(profile) => Some(profile),
+            _ => None,
+        })
+    }
+
+    /// The profile named `profile_name`, failing if more than one block has that name.
+    pub fn profile(&self, profile_name: &str) -> Result<Option<&ProfileBlock>, GenerateError> {
+        let matches = self
+            .profiles()
+            .filter(|profile| profile.name() == Some(profile_name))
+            .collect::<Vec<_>>();
+        match matches.as_slice() {
+            [] => Ok(None),
+            [profile] => Ok(Some(profile)),
+            _ => Err(GenerateError::DuplicateProfileName {
+                profile_name: profile_name.to_owned(),
+                count: matches.len(),
+            }),
+        }
+    }
+
+    fn require_profile(&self, profile_name: &str) -> Result<&ProfileBlock, GenerateError> {
+        self.profile(profile_name)?
+            .ok_or_else(|| GenerateError::ProfileNotFound {
+                profile_name: profile_name.to_owned(),
+            })
+    }
+}
+
+impl std::fmt::Display for KanshiConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl ConfigItem {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Whitespace(span) | Self::Comment(span) => *span,
+            Self::Output(output) => output.span,
+            Self::Profile(profile) => profile.span,
+            Self::Include(include) => include.span,
+            Self::Unknown(directive) => directive.span,
+        }
+    }
+}
+
+impl ProfileItem {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Whitespace(span) | Self::Comment(span) => *span,
+            Self::Output(output) => output.span,
+            Self::Exec(exec) => exec.span,
+            Self::Unknown(directive) => directive.span,
+        }
+    }
+}
+
+impl Word {
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl ProfileBlock {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(Word::value)
+    }
+
+    /// Span between the opening and closing brace, exclusive.
+    pub fn body(&self) -> Span {
+        self.body
+    }
+
+    pub fn items(&self) -> &[ProfileItem] {
+        &self.items
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = &OutputDirective> {
+        self.items.iter().filter_map(|item| match item {
+            ProfileItem::Output(output) => Some(output),
+            _ => None,
+        })
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl OutputDirective {
+    pub fn criteria(&self) -> &str {
+        self.criteria.value()
+    }
+
+    pub fn arguments(&self) -> &[Word] {
+        &self.arguments
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl ExecDirective {
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl IncludeDirective {
+    pub fn path(&self) -> &str {
+        self.path.value()
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl Directive {
+    pub fn keyword(&self) -> &str {
+        self.keyword.value()
+    }
+
+    pub fn arguments(&self) -> &[Word] {
+        &self.arguments
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl InputFormat {
+    /// Guesses the schema from the keys of the first output object.
+    pub fn detect(value: &serde_json::Value) -> Self {
+        let first = value
+            .as_array()
+            .and_then(|outputs| outputs.first())
+            .and_then(serde_json::Value::as_object);
+        match first {
+            Some(output) if output.contains_key("rect") || output.contains_key("active") => {
+                Self::Sway
+            }
+            Some(output)
+                if output.contains_key("availableModes") || output.contains_key("refreshRate") =>
+            {
+                Self::Hyprland
+            }
+            _ => Self::Wlr,
+        }
+    }
+}
+
+impl ProfileSettings {
+    /// Reads profile `profile_name` from kanshi config text, together with the
+    /// top-level `output` defaults that apply to every profile.
+    pub fn from_config(config: &str, profile_name: &str) -> Result<Self, GenerateError> {
+        let parsed = KanshiConfig::parse(config)?;
+        let profile = parsed.require_profile(profile_name)?;
+
+        let defaults = parsed
+            .items()
+            .iter()
+            .filter_map(|item| match item {
+                ConfigItem::Output(output) => Some(OutputSettings::from_directive(output)),
+                _ => None,
+            })
+            .collect::<Result<_, _>>()?;
+        let outputs = profile
+            .outputs()
+            .map(OutputSettings::from_directive)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { defaults, outputs })
+    }
+
+    /// Builds a profile that restores every output to its captured state.
+    pub fn from_outputs(outputs: &[OutputSnapshot]) -> Self {
+        let outputs = outputs
+            .iter()
+            .map(|output| {
+                let mode = output.enabled.then(|| output.active_mode()).flatten();
+                OutputSettings {
+                    criteria: output.padded_identifier(),
+                    enabled: Some(output.enabled),
+                    mode: mode.map(|mode| ModeSetting {
+                        width: mode.width,
+                        height: mode.height,
+                        refresh: Some(mode.refresh),
+                        custom: mode.custom_mode,
+                    }),
+                    position: output.position.clone().filter(|_| output.enabled),
+                    scale: output.scale.filter(|_| output.enabled),
+                    transform: output.normalized_transform().map(String::from),
+                    adaptive_sync: output.adaptive_sync.filter(|_| output.enabled),
+                }
+            })
+            .collect();
+        Self {
+            defaults: Vec::new(),
+            outputs,
+        }
+    }
+}
+
+impl OutputSettings {
+    fn from_directive(directive: &OutputDirective) -> Result<Self, GenerateError> {
+        let criteria = directive.criteria();
+        let invalid = |details: String| GenerateError::ConfigParse {
+            details: format!("output `{criteria}`: {details}"),
+        };
+
+        let mut settings = Self {
+            criteria: criteria.to_owned(),
+            ..Self::default()
+        };
+        let mut arguments = directive.arguments().iter().map(Word::value);
+        while let Some(argument) = arguments.next() {
+            let mut value = || {
+                arguments
+                    .next()
+                    .ok_or_else(|| invalid(format!("`{argument}` requires a value")))
+            };
+            match argument {
+                "enable" => settings.enabled = Some(true),
+                "disable" => settings.enabled = Some(false),
+                "mode" => {
+                    let mut mode = value()?;
+                    let custom = mode == "--custom";
+                    if custom {
+                        mode = value()?;
+                    }
+                    settings.mode = Some(
+                        parse_mode_setting(mode, custom)
+                            .ok_or_else(|| invalid(format!("invalid mode `{mode}`")))?,
+                    );
+                }
+                "position" => {
+                    let position = value()?;
+                    settings.position = Some(
+                        parse_position(position)
+                            .ok_or_else(|| invalid(format!("invalid position `{position}`")))?,
+                    );
+                }
+                "scale" => {
+                    let scale = value()?;
+                    settings.scale = Some(
+                        scale
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|scale| *scale > 0.0)
+                            .ok_or_else(|| invalid(format!("invalid scale `{scale}`")))?,
+                    );
+                }
+                "transform" => {
+                    let transform = value()?;
+                    settings.transform = Some(
+                        normalize_transform_str(transform)
+                            .ok_or_else(|| invalid(format!("invalid transform `{transform}`")))?
+                            .to_owned(),
+                    );
+                }
+                "adaptive_sync" => {
+                    settings.adaptive_sync = match value()? {
+                        "on" => Some(true),
+                        "off" => Some(false),
+                        other => return Err(invalid(format!("invalid adaptive_sync `{other}`"))),
+                    };
+                }
+                "alias" => {
+                    value()?;
+                }
+                other => return Err(invalid(format!("unknown output directive `{other}`"))),
+            }
+        }
+        Ok(settings)
+    }
+
+    fn describe(&self) -> String {
+        let mut description = format!("`{}`", self.criteria);
+        let mut properties = Vec::new();
+        match self.enabled {
+            Some(false) => properties.push(String::from("disable")),
+            Some(true) => properties.push(String::from("enable")),
+            None => {}
+        }
+        if let Some(mode) = &self.mode {
+            let mut property = format!("mode {}x{}", mode.width, mode.height);
+            if let Some(refresh) = mode.refresh {
+                let _ = write!(property, "@{refresh}Hz");
+            }
+            properties.push(property);
+        }
+        if let Some(position) = &self.position {
+            properties.push(format!("position {},{}", position.x, position.y));
+        }
+        if let Some(scale) = self.scale {
+            properties.push(format!("scale {scale}"));
+        }
+        if let Some(transform) = &self.transform {
+            properties.push(format!("transform {transform}"));
+        }
+        if !properties.is_empty() {
+            let _ = write!(description, " ({})", properties.join(" "));
+        }
+        description
+    }
+
+    fn merged_over(&self, defaults: Option<&Self>) -> Self {
+        let Some(defaults) = defaults else {
+            return self.clone();
+        };
+        Self {
+            criteria: self.criteria.clone(),
+            enabled: self.enabled.or(defaults.enabled),
+            mode: self.mode.clone().or_else(|| defaults.mode.clone()),
+            position: self.position.clone().or_else(|| defaults.position.clone()),
+            scale: self.scale.or(defaults.scale),
+            transform: self
+                .transform
+                .clone()
+                .or_else(|| defaults.transform.clone()),
+            adaptive_sync: self.adaptive_sync.or(defaults.adaptive_sync),
+        }
+    }
+}
+
+impl<'a> ConfigParser<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.as_bytes().get(self.position).copied()
+    }
+
+    fn error(&self, details: String) -> GenerateError {
+        GenerateError::ConfigParse { details }
+    }
+
+    fn parse_config(mut self) -> Result<Vec<ConfigItem>, GenerateError> {
+        let mut items = Vec::new();
+        while let Some(ch) = self.peek() {
+            let item = match ch {
+                b'#' => ConfigItem::Comment(self.comment()),
+                b'{' | b'}' => {
+                    return Err(self.error(format!(
+                        "unexpected `{}` at byte {}",
+                        char::from(ch),
+                        self.position
+                    )));
+                }
+                _ if ch.is_ascii_whitespace() => ConfigItem::Whitespace(self.whitespace()),
+                _ => {
+                    let keyword = self.word()?;
+                    match keyword.value.as_str() {
+                        "profile" => ConfigItem::Profile(self.profile(keyword)?),
+                        "output" => ConfigItem::Output(self.output(keyword)?),
+                        "include" => ConfigItem::Include(self.include(keyword)?),
+                        _ => ConfigItem::Unknown(self.directive(keyword)?),
+                    }
+                }
+            };
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    fn profile(&mut self, keyword: Word) -> Result<ProfileBlock, GenerateError> {
+        let start = keyword.span.start;
+        self.skip_whitespace_and_comments();
+        let name = match self.peek() {
+            Some(b'{' | b'}') | None => None,
+            Some(_) => {
+                let name = self.word()?;
+                self.skip_whitespace_and_comments();
+                Some(name)
+            }
+        };
+
+        if self.peek() != Some(b'{') {
+            return Err(self.error(format!(
+                "profile block starting at byte {start} has no opening brace"
+            )));
+        }
+        self.position += 1;
+
+        let body_start = self.position;
+        let items = self.profile_items()?;
+        if self.peek() != Some(b'}') {
+            let name = name.as_ref().map_or("<anonymous>", Word::value);
+            return Err(self.error(format!("profile `{name}` has an unclosed block")));
+        }
+        let body = Span {
+            start: body_start,
+            end: self.position,
+        };
+        self.position += 1;
+
+        Ok(ProfileBlock {
+            name,
+            body,
+            items,
+            span: Span {
+                start,
+                end: self.position,
+            },
+        })
+    }
+
+    fn profile_items(&mut self) -> Result<Vec<ProfileItem>, GenerateError> {
+        let mut items = Vec::new();
+        while let Some(ch) = self.peek() {
+            let item = match ch {
+                b'}' => break,
+                b'#' => ProfileItem::Comment(self.comment()),
+                b'{' => {
+                    return Err(self.error(format!("unexpected `{{` at byte {}", self.position)));
+                }
+                _ if ch.is_ascii_whitespace() => ProfileItem::Whitespace(self.whitespace()),
+                _ => {
+                    let keyword = self.word()?;
+                    match keyword.value.as_str() {
+                        "output" => ProfileItem::Output(self.output(keyword)?),
+                        "exec" => ProfileItem::Exec(self.exec(keyword)),
+                        _ => ProfileItem::Unknown(self.directive(keyword)?),
+                    }
+                }
+            };
+            items.push(item);
+        }
+        Ok(items)
+    }
+
+    fn output(&mut self, keyword: Word) -> Result<OutputDirective, GenerateError> {
+        let start = keyword.span.start;
+        let mut arguments = self.arguments()?;
+        if arguments.is_empty() {
+            return Err(self.error(format!("output directive at byte {start} has no criteria")));
+        }
+        let criteria = arguments.remove(0);
+        let end = arguments.last().unwrap_or(&criteria).span.end;
+        Ok(OutputDirective {
+            criteria,
+            arguments,
+            span: Span { start, end },
+        })
+    }
+
+    fn include(&mut self, keyword: Word) -> Result<IncludeDirective, GenerateError> {
+        let start = keyword.span.start;
+        let mut arguments = self.arguments()?;
+        if arguments.len() != 1 {
+            return Err(self.error(format!(
+                "include directive at byte {start} expects exactly one path"
+            )));
+        }
+        let path = arguments.remove(0);
+        let end = path.span.end;
+        Ok(IncludeDirective {
+            path,
+            span: Span { start, end },
+        })
+    }
+
+    /// kanshi passes the remainder of the line to the shell verbatim.
+    fn exec(&mut self, keyword: Word) -> ExecDirective {
+        let start = keyword.span.start;
+        let rest = &self.source[keyword.span.end..];
+        let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+        let command = line.trim();
+        let end = if command.is_empty() {
+            keyword.span.end
+        } else {
+            keyword.span.end + (line.len() - line.trim_start().len()) + command.len()
+        };
+        self.position = end;
+        ExecDirective {
+            command: command.to_owned(),
+            span: Span { start, end },
+        }
+    }
+
+    fn directive(&mut self, keyword: Word) -> Result<Directive, GenerateError> {
+        let start = keyword.span.start;
+        let arguments = self.arguments()?;
+        let end = arguments.last().unwrap_or(&keyword).span.end;
+        Ok(Directive {
+            keyword,
+            arguments,
+            span: Span { start, end },
+        })
+    }
+
+    /// Reads the words remaining on the current line, leaving trailing
+    /// whitespace and comments for the caller to record as trivia.
+    fn arguments(&mut self) -> Result<Vec<Word>, GenerateError> {
+        let mut arguments = Vec::new();
+        loop {
+            let before_whitespace = self.position;
+            while let Some(b' ' | b'\t' | b'\r') = self.peek() {
+                self.position += 1;
+            }
+            match self.peek() {
+                None | Some(b'\n' | b'#' | b'{' | b'}') => {
+                    self.position = before_whitespace;
+                    return Ok(arguments);
+                }
+                Some(_) => arguments.push(self.word()?),
+            }
+        }
+    }
+
+    fn word(&mut self) -> Result<Word, GenerateError> {
+        let start = self.position;
+        if self.peek() != Some(b'"') {
+            while let Some(ch) = self.peek() {
+                if ch.is_ascii_whitespace() || matches!(ch, b'{' | b'}') {
+                    break;
+                }
+                self.position += 1;
+            }
+            return Ok(Word {
+                value: self.source[start..self.position].to_owned(),
+                span: Span {
+                    start,
+                    end: self.position,
+                },
+            });
+        }
+
+        let mut value = String::new();
+        let mut escaped = false;
+        for (offset, ch) in self.source[start + 1..].char_indices() {
+            if escaped {
+                value.push(ch);
+                escaped = false;
+            } else if ch == '\\' {
+                escaped = true;
+            } else if ch == '"' {
+                self.position = start + 1 + offset + 1;
+                return Ok(Word {
+                    value,
+                    span: Span {
+                        start,
+                        end: self.position,
+                    },
+                });
+            } else {
+                value.push(ch);
+            }
+        }
+
+        Err(self.error(format!("unterminated string starting at byte {start}")))
+    }
+
+    fn whitespace(&mut self) -> Span {
+        let start = self.position;
+        while self.peek().is_some_and(|ch| ch.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+        Span {
+            start,
+            end: self.position,
+        }
+    }
+
+    fn comment(&mut self) -> Span {
+        let start = self.position;
+        while self.peek().is_some_and(|ch| ch != b'\n') {
+            self.position += 1;
+        }
+        Span {
+            start,
+            end: self.position,
+        }
+    }
+
+    fn skip_whitespace_and_comments(&mut self) {
+        loop {
+            match self.peek() {
+                Some(b'#') => {
+                    self.comment();
+                }
+                Some(ch) if ch.is_ascii_whitespace() => {
+                    self.whitespace();
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+fn render_profile(
+    profile_name: &str,
+    outputs: &[OutputSnapshot],
+    options: &RenderOptions,
+) -> Result<String, GenerateError> {
+    let mut criteria = unique_criteria(outputs, options.match_by)?;
+    if let Some(wildcard) = options.wildcard.as_ref() {
+        apply_wildcard(outputs, &mut criteria, wildcard)?;
+    }
+    let mut profile = String::with_capacity(32 + outputs.len() * 128);
+    writeln!(&mut profile, "profile {profile_name} {{").map_err(|_| GenerateError::Format)?;
+
+    for (output, criteria) in outputs.iter().zip(&criteria) {
+        let output_id = escape_quoted(criteria);
+        if output.enabled {
+            let EnabledState {
+                mode,
+                position,
+                scale,
+            } = output.enabled_state()?;
+            write!(
+                &mut profile,
+                "  output \"{output_id}\" mode {}{}x{}@{}Hz position {},{} scale {}",
+                custom_mode_flag(mode),
+                mode.width,
+                mode.height,
+                format_refresh(mode.refresh, options.refresh_precision),
+                position.x,
+                position.y,
+                format_scale(scale, options.scale_format)
+            )
+            .map_err(|_| GenerateError::Format)?;
+            if let Some(transform) = output.normalized_transform() {
+                write!(&mut profile, " transform {transform}")
+                    .map_err(|_| GenerateError::Format)?;
+            }
+            if let Some(adaptive_sync) = output.adaptive_sync {
+                write!(&mut profile, " adaptive_sync {}", on_off(adaptive_sync))
+                    .map_err(|_| GenerateError::Format)?;
+            }
+            profile.push('\n');
+        } else {
+            writeln!(&mut profile, "  output \"{output_id}\" disable")
+                .map_err(|_| GenerateError::Format)?;
+        }
+    }
+
+    profile.push_str("}\n");
+    Ok(profile)
+}
+
+/// `--custom ` for modes the output does not advertise, as kanshi and sway
+/// refuse to set those otherwise.
+fn custom_mode_flag(mode: &ModeSnapshot) -> &'static str {
+    if mode.custom_mode { "--custom " } else { "" }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled { "on" } else { "off" }
+}
+
+fn format_refresh(refresh: f64, precision: RefreshPrecision) -> String {
+    match precision {
+        RefreshPrecision::TwoDecimals => format!("{refresh:.2}"),
+        RefreshPrecision::ThreeDecimals => format!("{refresh:.3}"),
+        RefreshPrecision::Exact => format!("{}", f64::from(refresh_to_mhz(refresh)) / 1000.0),
+        RefreshPrecision::Rounded => format!("{refresh:.0}"),
+    }
+}
+
+fn format_scale(scale: f64, format: ScaleFormat) -> String {
+    match format {
+        ScaleFormat::Fixed => format!("{scale:.2}"),
+        ScaleFormat::Shortest => format!("{scale}"),
+    }
+}
+
+/// Criteria for every output such that each one matches a single output.
+/// With [`MatchBy::Auto`] outputs sharing an identifier, such as identical
+/// monitors without serials, fall back to their connector names; the explicit
+/// strategies fail instead.
+fn unique_criteria(
+    outputs: &[OutputSnapshot],
+    match_by: MatchBy,
+) -> Result<Vec<String>, GenerateError> {
+    let mut criteria = outputs
+        .iter()
+        .map(|output| output.criteria(match_by))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if match_by == MatchBy::Auto {
+        let shared = duplicate_criteria(&criteria)
+            .into_iter()
+            .flat_map(|(_, indices)| indices)
+            .collect::<Vec<_>>();
+        for index in shared {
+            criteria[index] = outputs[index].name.clone();
+        }
+    }
+
+    if let Some((identifier, indices)) = duplicate_criteria(&criteria).into_iter().next() {
+        return Err(GenerateError::AmbiguousOutputIdentifier {
+            identifier: identifier.to_owned(),
+            outputs: indices
+                .into_iter()
+                .map(|index| outputs[index].name.clone())
+                .collect(),
+        });
+    }
+    Ok(criteria)
+}
+
+/// Replaces the criteria of the outputs selected by `wildcard` with `*`.
+fn apply_wildcard(
+    outputs: &[OutputSnapshot],
+    criteria: &mut [String],
+    wildcard: &Wildcard,
+) -> Result<(), GenerateError> {
+    let selected = outputs
+        .iter()
+        .enumerate()
+        .filter(|(_, output)| match wildcard {
+            Wildcard::External => !output.is_internal(),
+            Wildcard::Output(selector) => output.is_selected_by(selector),
+        })
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    if selected.is_empty() {
+        return Err(match wildcard {
+            Wildcard::External => GenerateError::NoExternalOutput,
+            Wildcard::Output(selector) => GenerateError::UnknownOutput {
+                output: selector.clone(),
+            },
+        });
+    }
+    if selected.len() > 1 {
+        return Err(GenerateError::MultipleWildcards {
+            outputs: selected
+                .into_iter()
+                .map(|index| outputs[index].name.clone())
+                .collect(),
+        });
+    }
+
+    for index in selected {
+        criteria[index] = String::from("*");
+    }
+    Ok(())
+}
+
+/// Criteria used by more than one output, with the indices of those outputs.
+fn duplicate_criteria(criteria: &[String]) -> Vec<(&str, Vec<usize>)> {
+    let mut by_criteria = HashMap::<&str, Vec<usize>>::new();
+    for (index, criteria) in criteria.iter().enumerate() {
+        by_criteria.entry(criteria).or_default().push(index);
+    }
+    let mut duplicates = by_criteria
+        .into_iter()
+        .filter(|(_, indices)| indices.len() > 1)
+        .collect::<Vec<_>>();
+    duplicates.sort_by_key(|(_, indices)| indices[0]);
+    duplicates
+}
+
+fn render_sway(outputs: &[OutputSnapshot]) -> Result<String, GenerateError> {
+    let mut config = String::with_capacity(outputs.len() * 128);
+
+    for output in outputs {
+        let output_id = escape_quoted(&output.padded_identifier());
+        if output.enabled {
+            let EnabledState {
+                mode,
+                position,
+                scale,
+            } = output.enabled_state()?;
+            write!(
+                &mut config,
+                "output \"{output_id}\" mode {}{}x{}@{:.3}Hz pos {} {} scale {:.2}",
+                custom_mode_flag(mode),
+                mode.width,
+                mode.height,
+                mode.refresh,
+                position.x,
+                position.y,
+                scale
+            )
+            .map_err(|_| GenerateError::Format)?;
+            if let Some(transform) = output.normalized_transform() {
+                write!(&mut config, " transform {transform}").map_err(|_| GenerateError::Format)?;
+            }
+            if let Some(adaptive_sync) = output.adaptive_sync {
+                write!(&mut config, " adaptive_sync {}", on_off(adaptive_sync))
+                    .map_err(|_| GenerateError::Format)?;
+            }
+            config.push('\n');
+        } else {
+            writeln!(&mut config, "output \"{output_id}\" disable")
+                .map_err(|_| GenerateError::Format)?;
+        }
+    }
+
+    Ok(config)
+}
+
+fn render_hyprland(outputs: &[OutputSnapshot]) -> Result<String, GenerateError> {
+    let mut config = String::with_capacity(outputs.len() * 96);
+
+    for output in outputs {
+        let monitor = match output.description() {
+            Some(description) => format!("desc:{description}"),
+            None => output.name.clone(),
+        };
+        if output.enabled {
+            let EnabledState {
+                mode,
+                position,
+                scale,
+            } = output.enabled_state()?;
+            write!(
+                &mut config,
+                "monitor={monitor},{}x{}@{:.2},{}x{},{:.2}",
+                mode.width, mode.height, mode.refresh, position.x, position.y, scale
+            )
+            .map_err(|_| GenerateError::Format)?;
+            if let Some(transform) = output.normalized_transform().and_then(transform_index) {
+                write!(&mut config, ",transform,{transform}").map_err(|_| GenerateError::Format)?;
+            }
+            if let Some(adaptive_sync) = output.adaptive_sync {
+                write!(&mut config, ",vrr,{}", u8::from(adaptive_sync))
+                    .map_err(|_| GenerateError::Format)?;
+            }
+            config.push('\n');
+        } else {
+            writeln!(&mut config, "monitor={monitor},disable")
+                .map_err(|_| GenerateError::Format)?;
+        }
+    }
+
+    Ok(config)
+}
+
+fn render_niri(outputs: &[OutputSnapshot]) -> Result<String, GenerateError> {
+    let mut config = String::with_capacity(outputs.len() * 128);
+
+    for (index, output) in outputs.iter().enumerate() {
+        if index > 0 {
+            config.push('\n');
+        }
+        let output_id = escape_quoted(&output.padded_identifier());
+        writeln!(&mut config, "output \"{output_id}\" {{").map_err(|_| GenerateError::Format)?;
+        if output.enabled {
+            let EnabledState {
+                mode,
+                position,
+                scale,
+            } = output.enabled_state()?;
+            writeln!(
+                &mut config,
+                "    mode \"{}x{}@{:.3}\"",
+                mode.width, mode.height, mode.refresh
+            )
+            .map_err(|_| GenerateError::Format)?;
+            writeln!(&mut config, "    scale {scale:.2}").map_err(|_| GenerateError::Format)?;
+            if let Some(transform) = output.normalized_transform() {
+                writeln!(&mut config, "    transform \"{transform}\"")
+                    .map_err(|_| GenerateError::Format)?;
+            }
+            // niri only has an opt-in flag; VRR is off when it is absent.
+            if output.adaptive_sync == Some(true) {
+                config.push_str("    variable-refresh-rate\n");
+            }
+            writeln!(
+                &mut config,
+                "    position x={} y={}",
+                position.x, position.y
+            )
+            .map_err(|_| GenerateError::Format)?;
+        } else {
+            config.push_str("    off\n");
+        }
+        config.push_str("}\n");
+    }
+
+    Ok(config)
+}
+
+/// wlr-randr selects outputs by connector name only, so identifiers are not
+/// used here.
+fn render_wlr_randr(outputs: &[OutputSnapshot]) -> Result<String, GenerateError> {
+    let mut command = String::from("wlr-randr");
+
+    for output in outputs {
+        write!(
+            &mut command,
+            " \\\n  --output {}",
+            shell_quote(&output.name)
+        )
+        .map_err(|_| GenerateError::Format)?;
+        if output.enabled {
+            let EnabledState {
+                mode,
+                position,
+                scale,
+            } = output.enabled_state()?;
+            write!(
+                &mut command,
+                " --on --{}mode {}x{}@{:.3}Hz --pos {},{} --scale {:.2}",
+                if mode.custom_mode { "custom-" } else { "" },
+                mode.width,
+                mode.height,
+                mode.refresh,
+                position.x,
+                position.y,
+                scale
+            )
+            .map_err(|_| GenerateError::Format)?;
+            if let Some(transform) = output.normalized_transform() {
+                write!(&mut command, " --transform {transform}")
+                    .map_err(|_| GenerateError::Format)?;
+            }
+            if let Some(adaptive_sync) = output.adaptive_sync {
+                let state = if adaptive_sync { "enabled" } else { "disabled" };
+                write!(&mut command, " --adaptive-sync {state}")
+                    .map_err(|_| GenerateError::Format)?;
+            }
+        } else {
+            command.push_str(" --off");
+        }
+    }
+
+    command.push('\n');
+    Ok(command)
+}
+
+fn shell_quote(raw: &str) -> String {
+    let is_safe = |ch: char| ch.is_ascii_alphanumeric() || "-_.,:@/+=".contains(ch);
+    if !raw.is_empty() && raw.chars().all(is_safe) {
+        return raw.to_owned();
+    }
+    format!("'{}'", raw.replace('\'', "'\\''"))
+}
+
+/// Matches `text` against a kanshi criteria pattern where `*` stands for any
+/// run of characters.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let Some((prefix, rest)) = pattern.split_once('*') else {
+        return pattern == text;
+    };
+    let Some(mut remaining) = text.strip_prefix(prefix) else {
+        return false;
+    };
+
+    let mut segments = rest.split('*').peekable();
+    while let Some(segment) = segments.next() {
+        if segments.peek().is_none() {
+            return remaining.len() >= segment.len() && remaining.ends_with(segment);
+        }
+        match remaining.find(segment) {
+            Some(offset) => remaining = &remaining[offset + segment.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// Writes a profile name as a bare word when the config parser would read it
+/// back unchanged, and quoted otherwise.
+fn profile_name_token(name: &str) -> String {
+    let bare = !name.starts_with('"')
+        && !name.starts_with('#')
+        && !name
+            .bytes()
+            .any(|ch| ch.is_ascii_whitespace() || matches!(ch, b'{' | b'}'));
+    if bare {
+        name.to_owned()
+    } else {
+        format!("\"{}\"", escape_quoted(name))
+    }
+}
+
+fn escape_quoted(raw: &str) -> String {
+    let mut escaped = String::with_capacity(raw.len());
+    for ch in raw.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn transform_from_wayland(
+    transform: wayland_client::WEnum<wl_output::Transform>,
+) -> Option<String> {
+    match transform {
+        wayland_client::WEnum::Value(wl_output::Transform::Normal) => Some(String::from("normal")),
+        wayland_client::WEnum::Value(wl_output::Transform::_90) => Some(String::from("90")),
+        wayland_client::WEnum::Value(wl_output::Transform::_180) => Some(String::from("180")),
+        wayland_client::WEnum::Value(wl_output::Transform::_270) => Some(String::from("270")),
+        wayland_client::WEnum::Value(wl_output::Transform::Flipped) => {
+            Some(String::from("flipped"))
+        }
+        wayland_client::WEnum::Value(wl_output::Transform::Flipped90) => {
+            Some(String::from("flipped-90"))
+        }
+        wayland_client::WEnum::Value(wl_output::Transform::Flipped180) => {
+            Some(String::from("flipped-180"))
+        }
+        wayland_client::WEnum::Value(wl_output::Transform::Flipped270) => {
+            Some(String::from("flipped-270"))
+        }
+        wayland_client::WEnum::Value(_) => None,
+        wayland_client::WEnum::Unknown(raw) => normalize_transform_u32(raw).map(String::from),
+    }
+}
+
+fn transform_to_wayland(transform: &str) -> Option<wl_output::Transform> {
+    match normalize_transform_str(transform)? {
+        "normal" => Some(wl_output::Transform::Normal),
+        "90" => Some(wl_output::Transform::_90),
+        "180" => Some(wl_output::Transform::_180),
+        "270" => Some(wl_output::Transform::_270),
+        "flipped" => Some(wl_output::Transform::Flipped),
+        "flipped-90" => Some(wl_output::Transform::Flipped90),
+        "flipped-180" => Some(wl_output::Transform::Flipped180),
+        "flipped-270" => Some(wl_output::Transform::Flipped270),
+        _ => None,
+    }
+}
+
+fn normalize_transform_u32(raw: u32) -> Option<&'static str> {
+    match raw {
+        0 => Some("normal"),
+        1 => Some("90"),
         2 => Some("180"),
         3 => Some("270"),
         4 => Some("flipped"),
@@ -987,343 +3579,1593 @@ fn normalize_transform_u32(raw: u32) -> Option<&'static str> {
         7 => Some("flipped-270"),
         _ => None,
     }
-}
+}
+
+/// Inverse of [`normalize_transform_u32`].
+fn transform_index(transform: &str) -> Option<u32> {
+    (0..8).find(|index| normalize_transform_u32(*index) == Some(transform))
+}
+
+fn normalize_transform_str(raw: &str) -> Option<&'static str> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "normal" => Some("normal"),
+        "90" => Some("90"),
+        "180" => Some("180"),
+        "270" => Some("270"),
+        "flipped" => Some("flipped"),
+        "flipped-90" => Some("flipped-90"),
+        "flipped-180" => Some("flipped-180"),
+        "flipped-270" => Some("flipped-270"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::Path,
+        sync::{Mutex, OnceLock},
+        time::Duration,
+    };
+
+    use super::{
+        ConfigBackup, ConfigItem, ConfigWriteOptions, GenerateError, InputFormat, KanshiConfig,
+        MatchBy, ModeSetting, OutputFormat, OutputSnapshot, PositionSnapshot, ProfileItem,
+        ProfileSettings, ProfileWatcher, RefreshPrecision, RenderOptions, ScaleFormat,
+        UpsertOutcome, Wildcard, backup_timestamp, collect_outputs_from_json,
+        collect_outputs_from_json_as, dump_outputs_to_json, edit_config_file, filter_outputs,
+        generate_profile_from_outputs, generate_profile_from_slice, glob_matches,
+        list_config_backups, list_profiles_in_config, lock_config, match_profile_to_heads,
+        preview_upsert_profile_in_file, remove_profile_from_config, remove_profile_from_file,
+        rename_profile_in_config, rename_profile_in_file, render_outputs,
+        render_outputs_with_options, resolve_default_kanshi_config_path, restore_config_backup,
+        show_profile_in_config, upsert_profile_in_config, upsert_profile_in_file_with_options,
+        upsert_profile_in_file_with_outcome,
+    };
+
+    fn env_lock() -> &'static Mutex<()> {
+        static ENV_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+        ENV_LOCK.get_or_init(|| Mutex::new(()))
+    }
+
+    fn with_locked_env<T>(f: impl FnOnce() -> T) -> T {
+        let _guard = env_lock().lock().expect("env mutex poisoned");
+        let old_xdg = std::env::var_os("XDG_CONFIG_HOME");
+        let old_home = std::env::var_os("HOME");
+        let result = f();
+        unsafe {
+            match old_xdg {
+                Some(value) => std::env::set_var("XDG_CONFIG_HOME", value),
+                None => std::env::remove_var("XDG_CONFIG_HOME"),
+            }
+            match old_home {
+                Some(value) => std::env::set_var("HOME", value),
+                None => std::env::remove_var("HOME"),
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn renders_fixture_with_enabled_and_disabled_outputs() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let expected = include_str!("../tests/fixtures/mixed_outputs.kanshi");
+        let rendered = generate_profile_from_slice("docked", json.as_bytes()).unwrap();
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn renders_fixture_as_sway_output_commands() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let expected = include_str!("../tests/fixtures/mixed_outputs.sway");
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let rendered = render_outputs(OutputFormat::Sway, "docked", &outputs).unwrap();
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn renders_fixture_as_hyprland_monitor_lines() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let expected = include_str!("../tests/fixtures/mixed_outputs.hyprland");
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let rendered = render_outputs(OutputFormat::Hyprland, "docked", &outputs).unwrap();
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn hyprland_lines_use_connector_and_transform_index() {
+        let json = r#"[
+          {
+            "name":"HDMI-A-1",
+            "enabled":true,
+            "modes":[
+              {"width":1920,"height":1080,"refresh":60.0,"preferred":true,"current":true}
+            ],
+            "position":{"x":0,"y":0},
+            "scale":1.0,
+            "transform":"flipped-90"
+          }
+        ]"#;
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let rendered = render_outputs(OutputFormat::Hyprland, "desk", &outputs).unwrap();
+        assert_eq!(
+            rendered,
+            "monitor=HDMI-A-1,1920x1080@60.00,0x0,1.00,transform,5\n"
+        );
+    }
+
+    #[test]
+    fn renders_fixture_as_niri_output_blocks() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let expected = include_str!("../tests/fixtures/mixed_outputs.niri");
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let rendered = render_outputs(OutputFormat::Niri, "docked", &outputs).unwrap();
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn niri_blocks_include_transform_for_rotated_outputs() {
+        let json = r#"[
+          {
+            "name":"DP-7",
+            "make":"Dell Inc.",
+            "model":"DELL U2422H",
+            "serial":"75BNF83",
+            "enabled":true,
+            "modes":[
+              {"width":1920,"height":1080,"refresh":60.0,"preferred":true,"current":true}
+            ],
+            "position":{"x":0,"y":0},
+            "scale":1.0,
+            "transform":"90"
+          }
+        ]"#;
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let rendered = render_outputs(OutputFormat::Niri, "rotated", &outputs).unwrap();
+        assert!(rendered.contains("    transform \"90\"\n    position x=0 y=0\n"));
+    }
+
+    #[test]
+    fn renders_fixture_as_wlr_randr_command() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let expected = include_str!("../tests/fixtures/mixed_outputs.wlr-randr");
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let rendered = render_outputs(OutputFormat::WlrRandr, "docked", &outputs).unwrap();
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn wlr_randr_command_quotes_unusual_connector_names() {
+        let json = r#"[
+          {"name":"it's DP-1","enabled":false,"modes":[]}
+        ]"#;
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let rendered = render_outputs(OutputFormat::WlrRandr, "desk", &outputs).unwrap();
+        assert_eq!(rendered, "wlr-randr \\\n  --output 'it'\\''s DP-1' --off\n");
+    }
+
+    #[test]
+    fn renders_adaptive_sync_in_every_format() {
+        let json = r#"[
+          {
+            "name":"DP-1",
+            "enabled":true,
+            "modes":[
+              {"width":2560,"height":1440,"refresh":144.0,"preferred":true,"current":true}
+            ],
+            "position":{"x":0,"y":0},
+            "scale":1.0,
+            "adaptive_sync":true
+          },
+          {
+            "name":"HDMI-A-1",
+            "enabled":true,
+            "modes":[
+              {"width":1920,"height":1080,"refresh":60.0,"preferred":true,"current":true}
+            ],
+            "position":{"x":2560,"y":0},
+            "scale":1.0,
+            "adaptive_sync":false
+          }
+        ]"#;
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        assert_eq!(outputs[0].adaptive_sync, Some(true));
+
+        let render = |format| render_outputs(format, "gaming", &outputs).unwrap();
+        assert_eq!(
+            render(OutputFormat::Kanshi),
+            "profile gaming {\n  output \"DP-1\" mode 2560x1440@144.00Hz position 0,0 scale 1.00 adaptive_sync on\n  output \"HDMI-A-1\" mode 1920x1080@60.00Hz position 2560,0 scale 1.00 adaptive_sync off\n}\n"
+        );
+        let sway = render(OutputFormat::Sway);
+        assert!(sway.contains("scale 1.00 adaptive_sync on\n"));
+        assert!(sway.contains("scale 1.00 adaptive_sync off\n"));
+        let hyprland = render(OutputFormat::Hyprland);
+        assert!(hyprland.contains("monitor=DP-1,2560x1440@144.00,0x0,1.00,vrr,1\n"));
+        assert!(hyprland.contains("monitor=HDMI-A-1,1920x1080@60.00,2560x0,1.00,vrr,0\n"));
+        assert_eq!(
+            render(OutputFormat::Niri)
+                .matches("variable-refresh-rate")
+                .count(),
+            1
+        );
+        let wlr_randr = render(OutputFormat::WlrRandr);
+        assert!(wlr_randr.contains("--adaptive-sync enabled"));
+        assert!(wlr_randr.contains("--adaptive-sync disabled"));
+    }
+
+    #[test]
+    fn omits_adaptive_sync_when_not_reported() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let rendered = generate_profile_from_slice("docked", json.as_bytes()).unwrap();
+        assert!(!rendered.contains("adaptive_sync"));
+    }
+
+    #[test]
+    fn dumped_json_round_trips_through_json_collection() {
+        for (json, format) in [
+            (
+                include_str!("../tests/fixtures/mixed_outputs.json"),
+                InputFormat::Wlr,
+            ),
+            (
+                include_str!("../tests/fixtures/sway_outputs.json"),
+                InputFormat::Sway,
+            ),
+            (
+                include_str!("../tests/fixtures/hyprland_monitors.json"),
+                InputFormat::Hyprland,
+            ),
+        ] {
+            let outputs = collect_outputs_from_json_as(json.as_bytes(), format).unwrap();
+            let dumped = dump_outputs_to_json(&outputs).unwrap();
+            let value = serde_json::from_str(&dumped).unwrap();
+            assert_eq!(InputFormat::detect(&value), InputFormat::Wlr);
+
+            let replayed = collect_outputs_from_json(dumped.as_bytes()).unwrap();
+            assert_eq!(dump_outputs_to_json(&replayed).unwrap(), dumped);
+            assert_eq!(
+                generate_profile_from_outputs("docked", &replayed).unwrap(),
+                generate_profile_from_outputs("docked", &outputs).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn profile_watcher_only_follows_the_head_set_it_started_with() {
+        let docked = collect_outputs_from_json(
+            include_str!("../tests/fixtures/mixed_outputs.json").as_bytes(),
+        )
+        .unwrap();
+        let undocked = docked[..1].to_vec();
+        let mut watcher = ProfileWatcher::new();
+
+        assert!(watcher.is_watched_head_set(&docked));
+        let profile = generate_profile_from_outputs("docked", &docked).unwrap();
+        assert!(watcher.is_new_profile(&profile));
+        watcher.profile_written(profile.clone());
+
+        assert!(!watcher.is_watched_head_set(&undocked));
+        assert!(watcher.is_watched_head_set(&docked));
+        assert!(!watcher.is_new_profile(&profile));
+
+        let mut rearranged = docked.clone();
+        rearranged[2].position = Some(PositionSnapshot { x: 1920, y: 0 });
+        assert!(watcher.is_watched_head_set(&rearranged));
+        assert!(
+            watcher.is_new_profile(&generate_profile_from_outputs("docked", &rearranged).unwrap())
+        );
+    }
+
+    #[test]
+    fn json_collection_parses_fixture() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        assert_eq!(outputs.len(), 3);
+    }
+
+    #[test]
+    fn renders_sway_fixture() {
+        let json = include_str!("../tests/fixtures/sway_outputs.json");
+        let expected = include_str!("../tests/fixtures/sway_outputs.kanshi");
+        let outputs = collect_outputs_from_json_as(json.as_bytes(), InputFormat::Sway).unwrap();
+        let rendered = generate_profile_from_outputs("docked", &outputs).unwrap();
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn detects_sway_and_wlr_json() {
+        let sway = include_str!("../tests/fixtures/sway_outputs.json");
+        let wlr = include_str!("../tests/fixtures/mixed_outputs.json");
+        let detect = |json: &str| InputFormat::detect(&serde_json::from_str(json).unwrap());
+
+        assert_eq!(detect(sway), InputFormat::Sway);
+        assert_eq!(detect(wlr), InputFormat::Wlr);
+        assert_eq!(collect_outputs_from_json(sway.as_bytes()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn sway_current_mode_missing_from_mode_list_is_kept() {
+        let json = r#"[
+          {
+            "name":"HEADLESS-1",
+            "make":"headless",
+            "model":"headless",
+            "serial":"Unknown",
+            "active":true,
+            "modes":[],
+            "current_mode":{"width":1280,"height":720,"refresh":60000},
+            "rect":{"x":0,"y":0,"width":1280,"height":720},
+            "scale":1.0,
+            "transform":"normal"
+          }
+        ]"#;
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let rendered = generate_profile_from_outputs("headless", &outputs).unwrap();
+        assert!(rendered.contains(
+            "output \"headless headless\" mode 1280x720@60.00Hz position 0,0 scale 1.00"
+        ));
+    }
+
+    #[test]
+    fn current_mode_missing_from_advertised_modes_is_custom() {
+        let sway = r#"[
+          {
+            "name":"DP-1",
+            "make":"Dell Inc.",
+            "model":"U2723",
+            "serial":"ABC",
+            "active":true,
+            "modes":[{"width":2560,"height":1440,"refresh":59951}],
+            "current_mode":{"width":2560,"height":1440,"refresh":75000},
+            "rect":{"x":0,"y":0,"width":2560,"height":1440},
+            "scale":1.0,
+            "transform":"normal"
+          }
+        ]"#;
+        let outputs = collect_outputs_from_json_as(sway.as_bytes(), InputFormat::Sway).unwrap();
+        let rendered = generate_profile_from_outputs("desk", &outputs).unwrap();
+        assert!(rendered.contains("mode --custom 2560x1440@75.00Hz position 0,0"));
+
+        let hyprland = |available_modes: &str| {
+            format!(
+                r#"[{{"name":"DP-1","make":"Dell Inc.","model":"U2723","serial":"ABC",
+                     "width":2560,"height":1440,"refreshRate":75.0,"x":0,"y":0,"scale":1.0,
+                     "transform":0,"disabled":false{available_modes}}}]"#
+            )
+        };
+        for (available_modes, custom) in [
+            (r#","availableModes":["2560x1440@59.95Hz"]"#, true),
+            (r#","availableModes":[]"#, false),
+            ("", false),
+        ] {
+            let outputs = collect_outputs_from_json_as(
+                hyprland(available_modes).as_bytes(),
+                InputFormat::Hyprland,
+            )
+            .unwrap();
+            let rendered = generate_profile_from_outputs("desk", &outputs).unwrap();
+            assert_eq!(rendered.contains("mode --custom"), custom, "{rendered}");
+        }
+    }
+
+    #[test]
+    fn custom_modes_are_written_as_custom() {
+        let json = r#"[
+          {
+            "name":"DP-1",
+            "enabled":true,
+            "modes":[
+              {"width":2560,"height":1440,"refresh":59.951,"preferred":true,"current":false},
+              {"width":2560,"height":1440,"refresh":75.0,"preferred":false,"current":true,"custom_mode":true}
+            ],
+            "position":{"x":0,"y":0},
+            "scale":1.0
+          }
+        ]"#;
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let render = |format| render_outputs(format, "custom", &outputs).unwrap();
+
+        assert!(render(OutputFormat::Kanshi).contains("mode --custom 2560x1440@75.00Hz position"));
+        assert!(render(OutputFormat::Sway).contains("mode --custom 2560x1440@75.000Hz pos"));
+        assert!(render(OutputFormat::WlrRandr).contains("--custom-mode 2560x1440@75.000Hz"));
+
+        let profile = ProfileSettings::from_outputs(&outputs);
+        assert!(profile.outputs[0].mode.as_ref().unwrap().custom);
+    }
+
+    #[test]
+    fn renders_hyprland_fixture() {
+        let json = include_str!("../tests/fixtures/hyprland_monitors.json");
+        let expected = include_str!("../tests/fixtures/hyprland_monitors.kanshi");
+        let value = serde_json::from_str(json).unwrap();
+        assert_eq!(InputFormat::detect(&value), InputFormat::Hyprland);
+
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let rendered = generate_profile_from_outputs("docked", &outputs).unwrap();
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    fn hyprland_keeps_exact_refresh_of_current_mode() {
+        let json = include_str!("../tests/fixtures/hyprland_monitors.json");
+        let outputs = collect_outputs_from_json_as(json.as_bytes(), InputFormat::Hyprland).unwrap();
+        let current = outputs[2].active_mode().unwrap();
+        assert!(current.current);
+        assert_eq!(current.refresh, 59.951);
+        assert_eq!(outputs[2].modes.len(), 2);
+    }
+
+    #[test]
+    fn picks_current_mode_first() {
+        let json = r#"[
+          {
+            "name":"DP-1",
+            "make":"Dell",
+            "model":"U2723",
+            "serial":"ABC123",
+            "enabled":true,
+            "modes":[
+              {"width":1920,"height":1080,"refresh":60.0,"preferred":true,"current":false},
+              {"width":2560,"height":1440,"refresh":59.95,"preferred":false,"current":true}
+            ],
+            "position":{"x":10,"y":20},
+            "scale":1.0
+          }
+        ]"#;
+        let rendered = generate_profile_from_slice("desk", json.as_bytes()).unwrap();
+        assert!(rendered.contains("mode 2560x1440@59.95Hz"));
+    }
+
+    #[test]
+    fn falls_back_to_preferred_mode() {
+        let json = r#"[
+          {
+            "name":"DP-1",
+            "make":"Dell",
+            "model":"U2723",
+            "serial":"ABC123",
+            "enabled":true,
+            "modes":[
+              {"width":1920,"height":1080,"refresh":60.0,"preferred":true,"current":false}
+            ],
+            "position":{"x":0,"y":0},
+            "scale":1.0
+          }
+        ]"#;
+        let rendered = generate_profile_from_slice("desk", json.as_bytes()).unwrap();
+        assert!(rendered.contains("mode 1920x1080@60.00Hz"));
+    }
+
+    #[test]
+    fn errors_when_enabled_output_has_no_current_or_preferred_mode() {
+        let json = r#"[
+          {
+            "name":"DP-1",
+            "make":"Dell",
+            "model":"U2723",
+            "serial":"ABC123",
+            "enabled":true,
+            "modes":[
+              {"width":1920,"height":1080,"refresh":60.0,"preferred":false,"current":false}
+            ],
+            "position":{"x":0,"y":0},
+            "scale":1.0
+          }
+        ]"#;
+        let err = generate_profile_from_slice("desk", json.as_bytes()).unwrap_err();
+        assert!(matches!(err, GenerateError::MissingMode { .. }));
+    }
+
+    #[test]
+    fn keeps_negative_coordinates() {
+        let json = r#"[
+          {
+            "name":"DP-2",
+            "make":"Dell",
+            "model":"P2723D",
+            "serial":"2ZZ6714",
+            "enabled":true,
+            "modes":[
+              {"width":2560,"height":1440,"refresh":59.951,"preferred":true,"current":true}
+            ],
+            "position":{"x":-2560,"y":300},
+            "scale":1.25
+          }
+        ]"#;
+        let rendered = generate_profile_from_slice("desk", json.as_bytes()).unwrap();
+        assert!(rendered.contains("position -2560,300"));
+    }
+
+    #[test]
+    fn omits_unknown_serial_placeholder() {
+        let json = r#"[
+          {
+            "name":"eDP-1",
+            "make":"AU Optronics",
+            "model":"0xD291",
+            "serial":null,
+            "enabled":false,
+            "modes":[
+              {"width":1920,"height":1200,"refresh":60.0,"preferred":true,"current":false}
+            ]
+          }
+        ]"#;
+        let rendered = generate_profile_from_slice("mobile", json.as_bytes()).unwrap();
+        assert!(rendered.contains("output \"AU Optronics 0xD291\" disable"));
+        assert!(!rendered.contains("Unknown"));
+    }
+
+    #[test]
+    fn includes_transform_for_rotated_enabled_output() {
+        let json = r#"[
+          {
+            "name":"DP-7",
+            "make":"Dell Inc.",
+            "model":"DELL U2422H",
+            "serial":"75BNF83",
+            "enabled":true,
+            "modes":[
+              {"width":1920,"height":1080,"refresh":60.0,"preferred":true,"current":true}
+            ],
+            "position":{"x":0,"y":0},
+            "scale":1.0,
+            "transform":"90"
+          }
+        ]"#;
+        let rendered = generate_profile_from_slice("rotated", json.as_bytes()).unwrap();
+        assert!(rendered.contains("transform 90"));
+    }
+
+    #[test]
+    fn includes_transform_for_normal_orientation() {
+        let json = r#"[
+          {
+            "name":"DP-1",
+            "make":"Dell",
+            "model":"U2723",
+            "serial":"ABC123",
+            "enabled":true,
+            "modes":[
+              {"width":1920,"height":1080,"refresh":60.0,"preferred":true,"current":true}
+            ],
+            "position":{"x":0,"y":0},
+            "scale":1.0,
+            "transform":"normal"
+          }
+        ]"#;
+        let rendered = generate_profile_from_slice("desk", json.as_bytes()).unwrap();
+        assert!(rendered.contains("transform normal"));
+    }
+
+    #[test]
+    fn resolve_default_config_uses_xdg_config_home() {
+        with_locked_env(|| {
+            let temp = tempfile::TempDir::new().unwrap();
+            unsafe {
+                std::env::set_var("XDG_CONFIG_HOME", temp.path());
+                std::env::remove_var("HOME");
+            }
+
+            let path = resolve_default_kanshi_config_path().unwrap();
+            assert_eq!(path, temp.path().join("kanshi").join("config"));
+        });
+    }
+
+    #[test]
+    fn resolve_default_config_falls_back_to_home() {
+        with_locked_env(|| {
+            let temp = tempfile::TempDir::new().unwrap();
+            unsafe {
+                std::env::remove_var("XDG_CONFIG_HOME");
+                std::env::set_var("HOME", temp.path());
+            }
+
+            let path = resolve_default_kanshi_config_path().unwrap();
+            assert_eq!(
+                path,
+                temp.path().join(".config").join("kanshi").join("config")
+            );
+        });
+    }
+
+    #[test]
+    fn upsert_replaces_single_matching_block() {
+        let current = "# header\nprofile desk {\n  output \"old\" disable\n}\n\nprofile other {\n  output \"x\" disable\n}\n";
+        let replacement = "profile desk {\n  output \"new\" disable\n}\n";
+
+        let merged = upsert_profile_in_config(current, "desk", replacement).unwrap();
+
+        assert!(merged.contains("output \"new\" disable"));
+        assert!(merged.contains("profile other"));
+        assert!(!merged.contains("output \"old\" disable"));
+    }
+
+    #[test]
+    fn upsert_appends_when_profile_is_missing() {
+        let current = "profile alpha {\n  output \"x\" disable\n}";
+        let inserted = "profile beta {\n  output \"y\" disable\n}\n";
+
+        let merged = upsert_profile_in_config(current, "beta", inserted).unwrap();
+
+        assert!(merged.starts_with("profile alpha"));
+        assert!(merged.contains("\n\nprofile beta"));
+        assert!(merged.ends_with('\n'));
+    }
+
+    #[test]
+    fn upsert_file_outcome_replaced_existing() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_path = temp.path().join("config");
+        fs::write(
+            &config_path,
+            "profile desk {\n  output \"old\" disable\n}\n\nprofile other {\n}\n",
+        )
+        .unwrap();
+
+        let replacement = "profile desk {\n  output \"new\" disable\n}\n";
+        let outcome =
+            upsert_profile_in_file_with_outcome(&config_path, "desk", replacement).unwrap();
+
+        assert_eq!(outcome, UpsertOutcome::ReplacedExisting);
+        let updated = fs::read_to_string(config_path).unwrap();
+        assert!(updated.contains("output \"new\" disable"));
+        assert!(!updated.contains("output \"old\" disable"));
+    }
 
-fn normalize_transform_str(raw: &str) -> Option<&'static str> {
-    match raw.trim().to_ascii_lowercase().as_str() {
-        "normal" => Some("normal"),
-        "90" => Some("90"),
-        "180" => Some("180"),
-        "270" => Some("270"),
-        "flipped" => Some("flipped"),
-        "flipped-90" => Some("flipped-90"),
-        "flipped-180" => Some("flipped-180"),
-        "flipped-270" => Some("flipped-270"),
-        _ => None,
+    #[test]
+    fn upsert_file_outcome_appended_new() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_path = temp.path().join("config");
+        fs::write(&config_path, "profile alpha {\n  output \"x\" disable\n}\n").unwrap();
+
+        let inserted = "profile desk {\n  output \"new\" disable\n}\n";
+        let outcome = upsert_profile_in_file_with_outcome(&config_path, "desk", inserted).unwrap();
+
+        assert_eq!(outcome, UpsertOutcome::AppendedNew);
+        let updated = fs::read_to_string(config_path).unwrap();
+        assert!(updated.contains("profile alpha"));
+        assert!(updated.contains("profile desk"));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::{
-        fs,
-        path::Path,
-        sync::{Mutex, OnceLock},
-    };
+    #[test]
+    fn upsert_fails_on_duplicate_matching_profile_names() {
+        let current = "profile desk {\n}\nprofile desk {\n}\n";
+        let inserted = "profile desk {\n  output \"x\" disable\n}\n";
 
-    use super::{
-        GenerateError, UpsertOutcome, collect_outputs_from_json, generate_profile_from_slice,
-        resolve_default_kanshi_config_path, upsert_profile_in_config,
-        upsert_profile_in_file_with_outcome,
-    };
+        let err = upsert_profile_in_config(current, "desk", inserted).unwrap_err();
+        assert!(matches!(err, GenerateError::DuplicateProfileName { .. }));
+    }
 
-    fn env_lock() -> &'static Mutex<()> {
-        static ENV_LOCK: OnceLock<Mutex<()>> = OnceLock::new();
-        ENV_LOCK.get_or_init(|| Mutex::new(()))
+    #[test]
+    fn upsert_does_not_match_partial_profile_names() {
+        let current = "profile Home-21-9 {\n}\n";
+        let inserted = "profile Home {\n}\n";
+
+        let merged = upsert_profile_in_config(current, "Home", inserted).unwrap();
+        assert!(merged.contains("profile Home-21-9"));
+        assert!(merged.contains("profile Home {"));
     }
 
-    fn with_locked_env<T>(f: impl FnOnce() -> T) -> T {
-        let _guard = env_lock().lock().expect("env mutex poisoned");
-        let old_xdg = std::env::var_os("XDG_CONFIG_HOME");
-        let old_home = std::env::var_os("HOME");
-        let result = f();
-        unsafe {
-            match old_xdg {
-                Some(value) => std::env::set_var("XDG_CONFIG_HOME", value),
-                None => std::env::remove_var("XDG_CONFIG_HOME"),
-            }
-            match old_home {
-                Some(value) => std::env::set_var("HOME", value),
-                None => std::env::remove_var("HOME"),
+    #[test]
+    fn preview_reports_diff_without_writing() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_path = temp.path().join("config");
+        let current = "profile desk {\n  output \"old\" disable\n}\n";
+        fs::write(&config_path, current).unwrap();
+
+        let preview = preview_upsert_profile_in_file(
+            &config_path,
+            "desk",
+            "profile desk {\n  output \"new\" disable\n}\n",
+        )
+        .unwrap();
+
+        assert_eq!(preview.outcome(), UpsertOutcome::ReplacedExisting);
+        let diff = preview.unified_diff();
+        assert!(diff.contains("\n-  output \"old\" disable\n"));
+        assert!(diff.contains("\n+  output \"new\" disable\n"));
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), current);
+
+        let unchanged = preview_upsert_profile_in_file(&config_path, "desk", current).unwrap();
+        assert!(unchanged.is_unchanged());
+        assert_eq!(unchanged.unified_diff(), "");
+    }
+
+    #[test]
+    fn backup_timestamp_is_sortable_utc() {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_709_210_096_789);
+        assert_eq!(backup_timestamp(time), "20240229T123456.789Z");
+        assert_eq!(
+            backup_timestamp(std::time::UNIX_EPOCH),
+            "19700101T000000.000Z"
+        );
+    }
+
+    #[test]
+    fn config_rewrites_keep_rotating_backups() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_path = temp.path().join("config");
+        let options = ConfigWriteOptions {
+            backup_count: 2,
+            ..ConfigWriteOptions::default()
+        };
+        fs::write(&config_path, "profile v0 {\n}\n").unwrap();
+
+        for version in 1..=3 {
+            rename_profile_in_file(
+                &config_path,
+                &format!("v{}", version - 1),
+                &format!("v{version}"),
+                &options,
+            )
+            .unwrap();
+        }
+
+        let backups = list_config_backups(&config_path).unwrap();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(
+            fs::read_to_string(backups[0].path()).unwrap(),
+            "profile v2 {\n}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(backups[1].path()).unwrap(),
+            "profile v1 {\n}\n"
+        );
+
+        restore_config_backup(&config_path, &backups[1], &options).unwrap();
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "profile v1 {\n}\n"
+        );
+        let backups = list_config_backups(&config_path).unwrap();
+        assert_eq!(
+            fs::read_to_string(backups[0].path()).unwrap(),
+            "profile v3 {\n}\n"
+        );
+    }
+
+    #[test]
+    fn backups_within_one_millisecond_are_ordered_by_suffix_number() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_path = temp.path().join("config");
+        for suffix in ["", "-1", "-2", "-10"] {
+            fs::write(
+                temp.path().join(format!(
+                    "config.kanshi-generate.bak.20240229T123456.789Z{suffix}"
+                )),
+                suffix,
+            )
+            .unwrap();
+        }
+        fs::write(
+            temp.path()
+                .join("config.kanshi-generate.bak.20240229T123456.788Z-11"),
+            "older",
+        )
+        .unwrap();
+
+        let timestamps = list_config_backups(&config_path)
+            .unwrap()
+            .iter()
+            .map(|backup| backup.timestamp().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            timestamps,
+            [
+                "20240229T123456.789Z-10",
+                "20240229T123456.789Z-2",
+                "20240229T123456.789Z-1",
+                "20240229T123456.789Z",
+                "20240229T123456.788Z-11",
+            ]
+        );
+    }
+
+    #[test]
+    fn unchanged_rewrites_and_disabled_backups_leave_no_backup() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_path = temp.path().join("config");
+        let current = "profile desk {\n}\n";
+        fs::write(&config_path, current).unwrap();
+
+        upsert_profile_in_file_with_options(
+            &config_path,
+            "desk",
+            current,
+            &ConfigWriteOptions {
+                backup_count: 5,
+                ..ConfigWriteOptions::default()
+            },
+        )
+        .unwrap();
+        upsert_profile_in_file_with_outcome(&config_path, "desk", "profile desk {\n}\n\n").unwrap();
+        remove_profile_from_file(&config_path, "desk", &ConfigWriteOptions::default()).unwrap();
+
+        assert!(list_config_backups(&config_path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn config_rewrite_times_out_while_another_process_holds_the_lock() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_path = temp.path().join("config");
+        let current = "profile desk {\n}\n";
+        fs::write(&config_path, current).unwrap();
+
+        let holder = lock_config(&config_path, Duration::ZERO).unwrap();
+        let options = ConfigWriteOptions {
+            lock_timeout: Duration::from_millis(100),
+            ..ConfigWriteOptions::default()
+        };
+        let err = remove_profile_from_file(&config_path, "desk", &options).unwrap_err();
+        assert!(matches!(err, GenerateError::ConfigLocked { .. }));
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), current);
+
+        drop(holder);
+        remove_profile_from_file(&config_path, "desk", &options).unwrap();
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), "");
+    }
+
+    #[test]
+    fn config_modified_during_edit_is_not_clobbered() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_path = temp.path().join("config");
+        fs::write(&config_path, "profile desk {\n}\n").unwrap();
+        let user_edit = "profile desk {\n}\n\nprofile couch {\n}\n";
+
+        let err = edit_config_file(&config_path, &ConfigWriteOptions::default(), |existing| {
+            fs::write(&config_path, user_edit).unwrap();
+            Ok((format!("{existing}# generated\n"), ()))
+        })
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            GenerateError::ConfigModifiedConcurrently { .. }
+        ));
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), user_edit);
+        assert!(fs::read_dir(temp.path()).unwrap().all(|entry| {
+            !entry
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .ends_with(".tmp")
+        }));
+    }
+
+    #[test]
+    fn refused_config_rewrites_leave_backups_untouched() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_path = temp.path().join("config");
+        let options = ConfigWriteOptions {
+            backup_count: 2,
+            modification_retries: 3,
+            ..ConfigWriteOptions::default()
+        };
+        fs::write(&config_path, "profile desk {\n}\n").unwrap();
+        for revision in 0..2 {
+            edit_config_file(&config_path, &options, |existing| {
+                Ok((format!("{existing}# revision {revision}\n"), ()))
+            })
+            .unwrap();
+        }
+        let backups = list_config_backups(&config_path).unwrap();
+        assert_eq!(backups.len(), 2);
+
+        let err = edit_config_file(&config_path, &options, |existing| {
+            fs::write(&config_path, format!("{existing}# user edit\n")).unwrap();
+            Ok((format!("{existing}# generated\n"), ()))
+        })
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            GenerateError::ConfigModifiedConcurrently { .. }
+        ));
+
+        let unchanged = list_config_backups(&config_path).unwrap();
+        assert_eq!(
+            unchanged.iter().map(ConfigBackup::path).collect::<Vec<_>>(),
+            backups.iter().map(ConfigBackup::path).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn config_modified_during_edit_is_merged_again_when_retrying() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_path = temp.path().join("config");
+        fs::write(&config_path, "profile desk {\n}\n").unwrap();
+        let options = ConfigWriteOptions {
+            modification_retries: 1,
+            ..ConfigWriteOptions::default()
+        };
+
+        let mut calls = 0;
+        edit_config_file(&config_path, &options, |existing| {
+            calls += 1;
+            if calls == 1 {
+                fs::write(&config_path, "profile couch {\n}\n").unwrap();
             }
+            Ok((format!("{existing}# generated\n"), ()))
+        })
+        .unwrap();
+
+        assert_eq!(calls, 2);
+        assert_eq!(
+            fs::read_to_string(&config_path).unwrap(),
+            "profile couch {\n}\n# generated\n"
+        );
+    }
+
+    #[test]
+    fn config_rewrite_preserves_mode_and_extended_attributes() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let config_path = temp.path().join("config");
+        fs::write(&config_path, "profile desk {\n}\n").unwrap();
+        fs::set_permissions(&config_path, fs::Permissions::from_mode(0o600)).unwrap();
+        let has_xattr = xattr::set(&config_path, "user.kanshi-generate.test", b"kept").is_ok();
+
+        rename_profile_in_file(
+            &config_path,
+            "desk",
+            "office",
+            &ConfigWriteOptions::default(),
+        )
+        .unwrap();
+
+        let metadata = fs::metadata(&config_path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        if has_xattr {
+            assert_eq!(
+                xattr::get(&config_path, "user.kanshi-generate.test").unwrap(),
+                Some(b"kept".to_vec())
+            );
         }
-        result
     }
 
     #[test]
-    fn renders_fixture_with_enabled_and_disabled_outputs() {
-        let json = include_str!("../tests/fixtures/mixed_outputs.json");
-        let expected = include_str!("../tests/fixtures/mixed_outputs.kanshi");
-        let rendered = generate_profile_from_slice("docked", json.as_bytes()).unwrap();
-        assert_eq!(rendered, expected);
+    fn lists_and_shows_named_profiles() {
+        let current = "output * enable\n\nprofile alpha {\n  output \"A\" enable\n}\n\nprofile {\n}\n\nprofile beta {\n}\n";
+
+        assert_eq!(list_profiles_in_config(current).unwrap(), ["alpha", "beta"]);
+        assert_eq!(
+            show_profile_in_config(current, "alpha").unwrap(),
+            "profile alpha {\n  output \"A\" enable\n}"
+        );
+        let err = show_profile_in_config(current, "gamma").unwrap_err();
+        assert!(matches!(err, GenerateError::ProfileNotFound { .. }));
     }
 
     #[test]
-    fn json_collection_parses_fixture() {
-        let json = include_str!("../tests/fixtures/mixed_outputs.json");
-        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
-        assert_eq!(outputs.len(), 3);
+    fn remove_profile_keeps_single_blank_separator() {
+        let current = "# header\n\nprofile alpha {\n}\n\nprofile beta {\n}\n\nprofile gamma {\n}\n";
+
+        assert_eq!(
+            remove_profile_from_config(current, "beta").unwrap(),
+            "# header\n\nprofile alpha {\n}\n\nprofile gamma {\n}\n"
+        );
+        assert_eq!(
+            remove_profile_from_config(current, "gamma").unwrap(),
+            "# header\n\nprofile alpha {\n}\n\nprofile beta {\n}\n"
+        );
+        assert_eq!(
+            remove_profile_from_config("profile alpha {\n}\n\nprofile beta {\n}\n", "alpha")
+                .unwrap(),
+            "profile beta {\n}\n"
+        );
+        assert_eq!(
+            remove_profile_from_config("profile alpha {\n}\n", "alpha").unwrap(),
+            ""
+        );
     }
 
     #[test]
-    fn picks_current_mode_first() {
-        let json = r#"[
-          {
-            "name":"DP-1",
-            "make":"Dell",
-            "model":"U2723",
-            "serial":"ABC123",
-            "enabled":true,
-            "modes":[
-              {"width":1920,"height":1080,"refresh":60.0,"preferred":true,"current":false},
-              {"width":2560,"height":1440,"refresh":59.95,"preferred":false,"current":true}
-            ],
-            "position":{"x":10,"y":20},
-            "scale":1.0
-          }
-        ]"#;
-        let rendered = generate_profile_from_slice("desk", json.as_bytes()).unwrap();
-        assert!(rendered.contains("mode 2560x1440@59.95Hz"));
+    fn remove_profile_takes_trailing_comment_of_closing_line() {
+        let current = "profile alpha {\n} # laptop only\n\nprofile beta {\n}\n";
+
+        assert_eq!(
+            remove_profile_from_config(current, "alpha").unwrap(),
+            "profile beta {\n}\n"
+        );
+        assert_eq!(
+            remove_profile_from_config(current, "beta").unwrap(),
+            "profile alpha {\n} # laptop only\n"
+        );
     }
 
     #[test]
-    fn falls_back_to_preferred_mode() {
-        let json = r#"[
-          {
-            "name":"DP-1",
-            "make":"Dell",
-            "model":"U2723",
-            "serial":"ABC123",
-            "enabled":true,
-            "modes":[
-              {"width":1920,"height":1080,"refresh":60.0,"preferred":true,"current":false}
-            ],
-            "position":{"x":0,"y":0},
-            "scale":1.0
-          }
-        ]"#;
-        let rendered = generate_profile_from_slice("desk", json.as_bytes()).unwrap();
-        assert!(rendered.contains("mode 1920x1080@60.00Hz"));
+    fn remove_profile_rejects_duplicates() {
+        let current = "profile desk {\n}\nprofile desk {\n}\n";
+        let err = remove_profile_from_config(current, "desk").unwrap_err();
+        assert!(matches!(
+            err,
+            GenerateError::DuplicateProfileName { count: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn rename_profile_replaces_only_the_name() {
+        let current = "profile desk { # main\n  output \"A\" enable\n}\n";
+
+        assert_eq!(
+            rename_profile_in_config(current, "desk", "office").unwrap(),
+            "profile office { # main\n  output \"A\" enable\n}\n"
+        );
+        assert_eq!(
+            rename_profile_in_config(current, "desk", "home office").unwrap(),
+            "profile \"home office\" { # main\n  output \"A\" enable\n}\n"
+        );
+    }
+
+    #[test]
+    fn rename_profile_refuses_to_overwrite_existing_name() {
+        let current = "profile desk {\n}\nprofile office {\n}\n";
+        let err = rename_profile_in_config(current, "desk", "office").unwrap_err();
+        assert!(matches!(err, GenerateError::ProfileAlreadyExists { .. }));
+    }
+
+    #[test]
+    fn parser_ignores_profile_keyword_in_comments_and_strings() {
+        let current =
+            "# profile fake { }\nprofile desk {\n  output \"literal profile ignored\" disable\n}\n";
+        let inserted = "profile desk {\n  output \"new\" disable\n}\n";
+
+        let merged = upsert_profile_in_config(current, "desk", inserted).unwrap();
+        assert!(merged.starts_with("# profile fake { }\n"));
+        assert!(merged.contains("output \"new\" disable"));
+    }
+
+    #[test]
+    fn parser_reports_unclosed_profile_block() {
+        let current = "profile broken {\n  output \"x\" disable\n";
+        let inserted = "profile broken {\n}\n";
+
+        let err = upsert_profile_in_config(current, "broken", inserted).unwrap_err();
+        assert!(matches!(err, GenerateError::ConfigParse { .. }));
+    }
+
+    #[test]
+    fn config_parser_round_trips_source_byte_for_byte() {
+        let source = "# defaults\r\noutput eDP-1 scale 2\ninclude ~/.config/kanshi/extra\n\nprofile \"desk \\\"left\\\"\" {\n\toutput \"Dell Inc. DELL U2422H 75BNF83\" mode 1920x1080@60Hz # main\n  exec notify-send \"docked\"  \n}\nprofile{output * enable}";
+        let config = KanshiConfig::parse(source).unwrap();
+
+        assert_eq!(config.to_string(), source);
+        let mut covered = 0;
+        for item in config.items() {
+            assert_eq!(item.span().start(), covered);
+            covered = item.span().end();
+        }
+        assert_eq!(covered, source.len());
+    }
+
+    #[test]
+    fn config_parser_builds_typed_directives() {
+        let source = "output eDP-1 scale 2\ninclude extra.conf\nprofile desk {\n  output \"Dell Inc. DELL U2422H\" mode 1920x1080 position 0,0\n  exec swaymsg workspace 1  # keep\n}\n";
+        let config = KanshiConfig::parse(source).unwrap();
+
+        let ConfigItem::Output(defaults) = &config.items()[0] else {
+            panic!("expected top-level output defaults");
+        };
+        assert_eq!(defaults.criteria(), "eDP-1");
+        assert_eq!(defaults.arguments()[1].value(), "2");
+        assert!(
+            matches!(&config.items()[2], ConfigItem::Include(include) if include.path() == "extra.conf")
+        );
+
+        let profile = config.profiles().next().unwrap();
+        assert_eq!(profile.name(), Some("desk"));
+        let output = profile.outputs().next().unwrap();
+        assert_eq!(output.criteria(), "Dell Inc. DELL U2422H");
+        assert_eq!(
+            config.text(output.span()),
+            "output \"Dell Inc. DELL U2422H\" mode 1920x1080 position 0,0"
+        );
+        let exec = profile
+            .items()
+            .iter()
+            .find_map(|item| match item {
+                ProfileItem::Exec(exec) => Some(exec),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(exec.command(), "swaymsg workspace 1  # keep");
+    }
+
+    #[test]
+    fn config_parser_accepts_anonymous_profiles() {
+        let config = KanshiConfig::parse("profile {\n  output * enable\n}\n").unwrap();
+        let profile = config.profiles().next().unwrap();
+        assert_eq!(profile.name(), None);
+        assert_eq!(profile.outputs().next().unwrap().criteria(), "*");
+    }
+
+    #[test]
+    fn config_parser_reports_unterminated_string() {
+        let err = KanshiConfig::parse("profile desk {\n  output \"DP-1 disable\n}\n").unwrap_err();
+        assert!(matches!(err, GenerateError::ConfigParse { .. }));
     }
 
     #[test]
-    fn errors_when_enabled_output_has_no_current_or_preferred_mode() {
-        let json = r#"[
-          {
-            "name":"DP-1",
-            "make":"Dell",
-            "model":"U2723",
-            "serial":"ABC123",
-            "enabled":true,
-            "modes":[
-              {"width":1920,"height":1080,"refresh":60.0,"preferred":false,"current":false}
-            ],
-            "position":{"x":0,"y":0},
-            "scale":1.0
-          }
-        ]"#;
-        let err = generate_profile_from_slice("desk", json.as_bytes()).unwrap_err();
-        assert!(matches!(err, GenerateError::MissingMode { .. }));
+    fn profile_settings_read_output_directives_from_config() {
+        let config = "output eDP-1 scale 2\nprofile desk {\n  output eDP-1 disable\n  output \"Dell Inc. DELL U2422H 75BNF83\" mode 1920x1080@60Hz position -1920,0 transform 90 adaptive_sync on\n  exec true\n}\n";
+        let profile = ProfileSettings::from_config(config, "desk").unwrap();
+
+        assert_eq!(profile.defaults.len(), 1);
+        assert_eq!(profile.defaults[0].scale, Some(2.0));
+        assert_eq!(profile.outputs[0].enabled, Some(false));
+        let external = &profile.outputs[1];
+        assert_eq!(
+            external.mode,
+            Some(ModeSetting {
+                width: 1920,
+                height: 1080,
+                refresh: Some(60.0),
+                custom: false,
+            })
+        );
+        assert_eq!(external.position, Some(PositionSnapshot { x: -1920, y: 0 }));
+        assert_eq!(external.transform.as_deref(), Some("90"));
+        assert_eq!(external.adaptive_sync, Some(true));
     }
 
     #[test]
-    fn keeps_negative_coordinates() {
-        let json = r#"[
-          {
-            "name":"DP-2",
-            "make":"Dell",
-            "model":"P2723D",
-            "serial":"2ZZ6714",
-            "enabled":true,
-            "modes":[
-              {"width":2560,"height":1440,"refresh":59.951,"preferred":true,"current":true}
-            ],
-            "position":{"x":-2560,"y":300},
-            "scale":1.25
-          }
-        ]"#;
-        let rendered = generate_profile_from_slice("desk", json.as_bytes()).unwrap();
-        assert!(rendered.contains("position -2560,300"));
+    fn profile_settings_from_rendered_profile_use_written_refresh_rate() {
+        let json = include_str!("../tests/fixtures/fractional_outputs.json");
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let rendered = render_outputs_with_options(
+            OutputFormat::Kanshi,
+            "gaming",
+            &outputs,
+            &RenderOptions {
+                refresh_precision: RefreshPrecision::Rounded,
+                match_by: MatchBy::Connector,
+                ..RenderOptions::default()
+            },
+        )
+        .unwrap();
+
+        let captured = ProfileSettings::from_outputs(&outputs);
+        let written = ProfileSettings::from_config(&rendered, "gaming").unwrap();
+        let refresh = |profile: &ProfileSettings| {
+            profile.outputs[1]
+                .mode
+                .as_ref()
+                .and_then(|mode| mode.refresh)
+                .unwrap()
+        };
+        assert_eq!(refresh(&captured), 143.856);
+        assert_eq!(refresh(&written), 144.0);
+        assert_eq!(written.outputs[1].criteria, "DP-1");
     }
 
     #[test]
-    fn omits_unknown_serial_placeholder() {
-        let json = r#"[
-          {
-            "name":"eDP-1",
-            "make":"AU Optronics",
-            "model":"0xD291",
-            "serial":null,
-            "enabled":false,
-            "modes":[
-              {"width":1920,"height":1200,"refresh":60.0,"preferred":true,"current":false}
-            ]
-          }
-        ]"#;
-        let rendered = generate_profile_from_slice("mobile", json.as_bytes()).unwrap();
-        assert!(rendered.contains("output \"AU Optronics 0xD291\" disable"));
-        assert!(!rendered.contains("Unknown"));
+    fn custom_modes_too_large_for_the_protocol_are_rejected() {
+        let mode = ModeSetting {
+            width: 3840,
+            height: 2160,
+            refresh: Some(59.997),
+            custom: true,
+        };
+        assert_eq!(mode.protocol_size("DP-1").unwrap(), (3840, 2160));
+        assert_eq!(mode.to_string(), "3840x2160@59.997Hz");
+
+        let oversized = ModeSetting {
+            width: u32::MAX,
+            refresh: None,
+            ..mode
+        };
+        let err = oversized.protocol_size("DP-1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "mode 4294967295x2160 for output `DP-1` is too large"
+        );
     }
 
     #[test]
-    fn includes_transform_for_rotated_enabled_output() {
-        let json = r#"[
-          {
-            "name":"DP-7",
-            "make":"Dell Inc.",
-            "model":"DELL U2422H",
-            "serial":"75BNF83",
-            "enabled":true,
-            "modes":[
-              {"width":1920,"height":1080,"refresh":60.0,"preferred":true,"current":true}
-            ],
-            "position":{"x":0,"y":0},
-            "scale":1.0,
-            "transform":"90"
-          }
-        ]"#;
-        let rendered = generate_profile_from_slice("rotated", json.as_bytes()).unwrap();
-        assert!(rendered.contains("transform 90"));
+    fn profile_settings_reject_unknown_output_directives() {
+        let config = "profile desk {\n  output DP-1 brightness 50\n}\n";
+        let err = ProfileSettings::from_config(config, "desk").unwrap_err();
+        assert!(matches!(err, GenerateError::ConfigParse { .. }));
+
+        let err = ProfileSettings::from_config(config, "missing").unwrap_err();
+        assert!(matches!(err, GenerateError::ProfileNotFound { .. }));
     }
 
     #[test]
-    fn includes_transform_for_normal_orientation() {
-        let json = r#"[
-          {
-            "name":"DP-1",
-            "make":"Dell",
-            "model":"U2723",
-            "serial":"ABC123",
-            "enabled":true,
-            "modes":[
-              {"width":1920,"height":1080,"refresh":60.0,"preferred":true,"current":true}
-            ],
-            "position":{"x":0,"y":0},
-            "scale":1.0,
-            "transform":"normal"
-          }
-        ]"#;
-        let rendered = generate_profile_from_slice("desk", json.as_bytes()).unwrap();
-        assert!(rendered.contains("transform normal"));
+    fn profile_matching_prefers_exact_criteria_over_wildcard() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let heads = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let config = "output eDP-1 scale 1.5\nprofile desk {\n  output * enable\n  output \"Dell Inc. DELL P2723D 2ZZ6714\" disable\n  output DP-1 enable\n}\n";
+        let profile = ProfileSettings::from_config(config, "desk").unwrap();
+
+        let assignments = match_profile_to_heads(&heads, &profile).unwrap();
+
+        let criteria = assignments
+            .iter()
+            .map(|settings| settings.as_ref().unwrap().criteria.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(criteria, ["*", "DP-1", "Dell Inc. DELL P2723D 2ZZ6714"]);
+        assert_eq!(assignments[0].as_ref().unwrap().scale, Some(1.5));
     }
 
     #[test]
-    fn resolve_default_config_uses_xdg_config_home() {
-        with_locked_env(|| {
-            let temp = tempfile::TempDir::new().unwrap();
-            unsafe {
-                std::env::set_var("XDG_CONFIG_HOME", temp.path());
-                std::env::remove_var("HOME");
-            }
+    fn profile_matching_expands_make_model_patterns() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let heads = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let config = "profile desk {\n  output * disable\n  output \"Dell Inc. DELL P2723D *\" enable\n  output \"AU Optronics 0xD291 *\" enable\n}\n";
+        let profile = ProfileSettings::from_config(config, "desk").unwrap();
 
-            let path = resolve_default_kanshi_config_path().unwrap();
-            assert_eq!(path, temp.path().join("kanshi").join("config"));
-        });
+        let assignments = match_profile_to_heads(&heads, &profile).unwrap();
+
+        let criteria = assignments
+            .iter()
+            .map(|settings| settings.as_ref().unwrap().criteria.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            criteria,
+            ["AU Optronics 0xD291 *", "*", "Dell Inc. DELL P2723D *"]
+        );
     }
 
     #[test]
-    fn resolve_default_config_falls_back_to_home() {
-        with_locked_env(|| {
-            let temp = tempfile::TempDir::new().unwrap();
-            unsafe {
-                std::env::remove_var("XDG_CONFIG_HOME");
-                std::env::set_var("HOME", temp.path());
-            }
+    fn exact_criteria_match_the_padded_identifier() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let heads = collect_outputs_from_json(json.as_bytes()).unwrap();
 
-            let path = resolve_default_kanshi_config_path().unwrap();
-            assert_eq!(
-                path,
-                temp.path().join(".config").join("kanshi").join("config")
-            );
-        });
+        assert!(heads[0].matches_criteria("eDP-1"));
+        assert!(heads[0].matches_criteria("AU Optronics 0xD291 Unknown"));
+        assert!(!heads[0].matches_criteria("AU Optronics 0xD291"));
     }
 
     #[test]
-    fn upsert_replaces_single_matching_block() {
-        let current = "# header\nprofile desk {\n  output \"old\" disable\n}\n\nprofile other {\n  output \"x\" disable\n}\n";
-        let replacement = "profile desk {\n  output \"new\" disable\n}\n";
+    fn glob_matching_handles_leading_inner_and_trailing_wildcards() {
+        assert!(glob_matches("*", "anything"));
+        assert!(glob_matches("Dell Inc. *", "Dell Inc. DELL U2422H 75BNF83"));
+        assert!(glob_matches("* U2422H *", "Dell Inc. DELL U2422H 75BNF83"));
+        assert!(glob_matches("*75BNF83", "Dell Inc. DELL U2422H 75BNF83"));
+        assert!(!glob_matches("Dell Inc. *", "LG Electronics 27GL850"));
+        assert!(!glob_matches("DP-1", "DP-10"));
+        assert!(!glob_matches("ab*ba", "aba"));
+    }
 
-        let merged = upsert_profile_in_config(current, "desk", replacement).unwrap();
+    #[test]
+    fn profile_matching_reports_unconnected_outputs() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let heads = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let profile =
+            ProfileSettings::from_config("profile desk {\n  output HDMI-A-1 enable\n}\n", "desk")
+                .unwrap();
+
+        let err = match_profile_to_heads(&heads, &profile).unwrap_err();
+        assert!(matches!(
+            err,
+            GenerateError::ProfileOutputNotConnected { criteria } if criteria == "HDMI-A-1"
+        ));
+    }
 
-        assert!(merged.contains("output \"new\" disable"));
-        assert!(merged.contains("profile other"));
-        assert!(!merged.contains("output \"old\" disable"));
+    #[test]
+    fn rejected_configuration_names_outputs_and_settings() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let profile = ProfileSettings::from_outputs(&outputs);
+
+        let err = GenerateError::OutputConfigurationRejected {
+            outputs: vec![profile.outputs[0].describe(), profile.outputs[2].describe()],
+        };
+
+        assert_eq!(
+            err.to_string(),
+            "compositor rejected the output configuration for `AU Optronics 0xD291 Unknown` (disable), \
+             `Dell Inc. DELL P2723D 2ZZ6714` (enable mode 2560x1440@59.951Hz position -2560,300 scale 1.25)"
+        );
     }
 
     #[test]
-    fn upsert_appends_when_profile_is_missing() {
-        let current = "profile alpha {\n  output \"x\" disable\n}";
-        let inserted = "profile beta {\n  output \"y\" disable\n}\n";
+    fn match_by_selects_kanshi_output_criteria() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let render = |match_by| {
+            render_outputs_with_options(
+                OutputFormat::Kanshi,
+                "docked",
+                &outputs,
+                &RenderOptions {
+                    match_by,
+                    ..RenderOptions::default()
+                },
+            )
+            .unwrap()
+        };
 
-        let merged = upsert_profile_in_config(current, "beta", inserted).unwrap();
+        let auto = render(MatchBy::Auto);
+        assert_eq!(auto, include_str!("../tests/fixtures/mixed_outputs.kanshi"));
+        assert_eq!(render(MatchBy::Description), auto);
 
-        assert!(merged.starts_with("profile alpha"));
-        assert!(merged.contains("\n\nprofile beta"));
-        assert!(merged.ends_with('\n'));
+        let connector = render(MatchBy::Connector);
+        assert!(connector.contains("  output \"eDP-1\" disable\n"));
+        assert!(connector.contains("  output \"DP-2\" mode 2560x1440@59.95Hz"));
+
+        let make_model = render(MatchBy::MakeModel);
+        assert!(make_model.contains("  output \"AU Optronics 0xD291 *\" disable\n"));
+        assert!(make_model.contains("  output \"Dell Inc. DELL U2422H *\" mode"));
     }
 
     #[test]
-    fn upsert_file_outcome_replaced_existing() {
-        let temp = tempfile::TempDir::new().unwrap();
-        let config_path = temp.path().join("config");
-        fs::write(
-            &config_path,
-            "profile desk {\n  output \"old\" disable\n}\n\nprofile other {\n}\n",
-        )
-        .unwrap();
+    fn match_by_description_requires_make_model_or_serial() {
+        let json = r#"[{"name":"HEADLESS-1","make":"","model":"","serial":null,"enabled":false,"modes":[]}]"#;
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
 
-        let replacement = "profile desk {\n  output \"new\" disable\n}\n";
-        let outcome =
-            upsert_profile_in_file_with_outcome(&config_path, "desk", replacement).unwrap();
+        for (match_by, expected) in [(MatchBy::Description, true), (MatchBy::MakeModel, false)] {
+            let err = render_outputs_with_options(
+                OutputFormat::Kanshi,
+                "headless",
+                &outputs,
+                &RenderOptions {
+                    match_by,
+                    ..RenderOptions::default()
+                },
+            )
+            .unwrap_err();
+            assert_eq!(
+                matches!(err, GenerateError::MissingDescription { .. }),
+                expected
+            );
+            assert_eq!(
+                matches!(err, GenerateError::MissingMakeModel { .. }),
+                !expected
+            );
+        }
+    }
 
-        assert_eq!(outcome, UpsertOutcome::ReplacedExisting);
-        let updated = fs::read_to_string(config_path).unwrap();
-        assert!(updated.contains("output \"new\" disable"));
-        assert!(!updated.contains("output \"old\" disable"));
+    #[test]
+    fn compositor_description_is_captured_but_not_matched() {
+        let json = r#"[
+          {
+            "name":"HEADLESS-1",
+            "make":"",
+            "model":"",
+            "serial":null,
+            "description":"Headless output 1",
+            "physical_size":{"width":0,"height":0},
+            "enabled":false,
+            "modes":[]
+          },
+          {
+            "name":"DP-1",
+            "make":"Dell Inc.",
+            "model":"DELL U2422H",
+            "serial":"75BNF83",
+            "description":"Dell Inc. DELL U2422H 75BNF83 (DP-1)",
+            "physical_size":{"width":527,"height":296},
+            "enabled":false,
+            "modes":[]
+          }
+        ]"#;
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        assert!(outputs[0].physical_size().is_none());
+        let physical_size = outputs[1].physical_size().unwrap();
+        assert_eq!((physical_size.width(), physical_size.height()), (527, 296));
+
+        assert_eq!(outputs[0].description.as_deref(), Some("Headless output 1"));
+
+        let rendered = generate_profile_from_outputs("headless", &outputs).unwrap();
+        assert_eq!(
+            rendered,
+            "profile headless {\n  output \"HEADLESS-1\" disable\n  output \"Dell Inc. DELL U2422H 75BNF83\" disable\n}\n"
+        );
+        let err = render_outputs_with_options(
+            OutputFormat::Kanshi,
+            "headless",
+            &outputs,
+            &RenderOptions {
+                match_by: MatchBy::Description,
+                ..RenderOptions::default()
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, GenerateError::MissingDescription { .. }));
     }
 
     #[test]
-    fn upsert_file_outcome_appended_new() {
-        let temp = tempfile::TempDir::new().unwrap();
-        let config_path = temp.path().join("config");
-        fs::write(&config_path, "profile alpha {\n  output \"x\" disable\n}\n").unwrap();
+    fn identical_monitors_fall_back_to_connector_names() {
+        let json = r#"[
+            {"name":"eDP-1","make":"BOE","model":"0x0BCA","serial":null,"enabled":false,"modes":[]},
+            {"name":"DP-1","make":"LG Electronics","model":"LG HDR 4K","serial":null,"enabled":false,"modes":[]},
+            {"name":"DP-2","make":"LG Electronics","model":"LG HDR 4K","serial":null,"enabled":false,"modes":[]}
+        ]"#;
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
 
-        let inserted = "profile desk {\n  output \"new\" disable\n}\n";
-        let outcome = upsert_profile_in_file_with_outcome(&config_path, "desk", inserted).unwrap();
+        let rendered = generate_profile_from_outputs("twins", &outputs).unwrap();
+        assert_eq!(
+            rendered,
+            "profile twins {\n  output \"BOE 0x0BCA\" disable\n  output \"DP-1\" disable\n  output \"DP-2\" disable\n}\n"
+        );
+
+        let err = render_outputs_with_options(
+            OutputFormat::Kanshi,
+            "twins",
+            &outputs,
+            &RenderOptions {
+                match_by: MatchBy::Description,
+                ..RenderOptions::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "outputs DP-1, DP-2 share the identifier `LG Electronics LG HDR 4K` and cannot be told apart"
+        );
+    }
 
-        assert_eq!(outcome, UpsertOutcome::AppendedNew);
-        let updated = fs::read_to_string(config_path).unwrap();
-        assert!(updated.contains("profile alpha"));
-        assert!(updated.contains("profile desk"));
+    #[test]
+    fn make_model_rejects_identical_monitors_with_distinct_serials() {
+        let json = r#"[
+            {"name":"DP-1","make":"Dell Inc.","model":"DELL U2422H","serial":"A","enabled":false,"modes":[]},
+            {"name":"DP-2","make":"Dell Inc.","model":"DELL U2422H","serial":"B","enabled":false,"modes":[]}
+        ]"#;
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+
+        assert!(generate_profile_from_outputs("twins", &outputs).is_ok());
+        let err = render_outputs_with_options(
+            OutputFormat::Kanshi,
+            "twins",
+            &outputs,
+            &RenderOptions {
+                match_by: MatchBy::MakeModel,
+                ..RenderOptions::default()
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            GenerateError::AmbiguousOutputIdentifier { outputs, .. } if outputs == ["DP-1", "DP-2"]
+        ));
     }
 
     #[test]
-    fn upsert_fails_on_duplicate_matching_profile_names() {
-        let current = "profile desk {\n}\nprofile desk {\n}\n";
-        let inserted = "profile desk {\n  output \"x\" disable\n}\n";
+    fn wildcard_replaces_selected_output_criteria() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let render = |wildcard| {
+            render_outputs_with_options(
+                OutputFormat::Kanshi,
+                "docked",
+                &outputs,
+                &RenderOptions {
+                    wildcard: Some(wildcard),
+                    ..RenderOptions::default()
+                },
+            )
+        };
 
-        let err = upsert_profile_in_config(current, "desk", inserted).unwrap_err();
-        assert!(matches!(err, GenerateError::DuplicateProfileName { .. }));
+        let rendered = render(Wildcard::Output(String::from(
+            "Dell Inc. DELL P2723D 2ZZ6714",
+        )))
+        .unwrap();
+        assert!(rendered.contains("  output \"*\" mode 2560x1440@59.95Hz position -2560,300"));
+        assert!(rendered.contains("  output \"Dell Inc. DELL U2422H 75BNF83\" mode"));
+
+        let err = render(Wildcard::External).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "outputs DP-1, DP-2 would all become `*`, but a kanshi profile can have at most one wildcard output"
+        );
+
+        let err = render(Wildcard::Output(String::from("HDMI-A-1"))).unwrap_err();
+        assert!(matches!(err, GenerateError::UnknownOutput { output } if output == "HDMI-A-1"));
     }
 
     #[test]
-    fn upsert_does_not_match_partial_profile_names() {
-        let current = "profile Home-21-9 {\n}\n";
-        let inserted = "profile Home {\n}\n";
+    fn wildcard_external_keeps_built_in_panel() {
+        let json = r#"[
+            {"name":"eDP-1","make":"BOE","model":"0x0BCA","serial":null,"enabled":false,"modes":[]},
+            {"name":"HDMI-A-1","make":"LG Electronics","model":"LG HDR 4K","serial":"X","enabled":false,"modes":[]}
+        ]"#;
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
 
-        let merged = upsert_profile_in_config(current, "Home", inserted).unwrap();
-        assert!(merged.contains("profile Home-21-9"));
-        assert!(merged.contains("profile Home {"));
+        let rendered = render_outputs_with_options(
+            OutputFormat::Kanshi,
+            "laptop-plus-any",
+            &outputs,
+            &RenderOptions {
+                wildcard: Some(Wildcard::External),
+                ..RenderOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            rendered,
+            "profile laptop-plus-any {\n  output \"BOE 0x0BCA\" disable\n  output \"*\" disable\n}\n"
+        );
+
+        let err = render_outputs_with_options(
+            OutputFormat::Kanshi,
+            "laptop-plus-any",
+            &outputs[..1],
+            &RenderOptions {
+                wildcard: Some(Wildcard::External),
+                ..RenderOptions::default()
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, GenerateError::NoExternalOutput));
     }
 
     #[test]
-    fn parser_ignores_profile_keyword_in_comments_and_strings() {
-        let current =
-            "# profile fake { }\nprofile desk {\n  output \"literal profile ignored\" disable\n}\n";
-        let inserted = "profile desk {\n  output \"new\" disable\n}\n";
+    fn filter_outputs_by_connector_or_identifier() {
+        let json = include_str!("../tests/fixtures/mixed_outputs.json");
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let names = |filtered: Vec<OutputSnapshot>| {
+            filtered
+                .into_iter()
+                .map(|output| output.name)
+                .collect::<Vec<_>>()
+        };
 
-        let merged = upsert_profile_in_config(current, "desk", inserted).unwrap();
-        assert!(merged.starts_with("# profile fake { }\n"));
-        assert!(merged.contains("output \"new\" disable"));
+        let only = [
+            String::from("eDP-1"),
+            String::from("Dell Inc. DELL P2723D 2ZZ6714"),
+        ];
+        assert_eq!(
+            names(filter_outputs(&outputs, &only, &[]).unwrap()),
+            ["eDP-1", "DP-2"]
+        );
+        assert_eq!(
+            names(filter_outputs(&outputs, &[], &[String::from("DP-1")]).unwrap()),
+            ["eDP-1", "DP-2"]
+        );
+        assert_eq!(
+            names(filter_outputs(&outputs, &only, &[String::from("eDP-1")]).unwrap()),
+            ["DP-2"]
+        );
+
+        let err = filter_outputs(&outputs, &[], &[String::from("HDMI-A-1")]).unwrap_err();
+        assert!(matches!(err, GenerateError::UnknownOutput { output } if output == "HDMI-A-1"));
+
+        let dp1 = [String::from("DP-1")];
+        let err = filter_outputs(&outputs, &dp1, &dp1).unwrap_err();
+        assert!(matches!(err, GenerateError::NoOutputsSelected));
+        let all = [
+            String::from("eDP-1"),
+            String::from("DP-1"),
+            String::from("DP-2"),
+        ];
+        let err = filter_outputs(&outputs, &[], &all).unwrap_err();
+        assert!(matches!(err, GenerateError::NoOutputsSelected));
     }
 
     #[test]
-    fn parser_reports_unclosed_profile_block() {
-        let current = "profile broken {\n  output \"x\" disable\n";
-        let inserted = "profile broken {\n}\n";
+    fn refresh_precision_and_scale_format_control_kanshi_numbers() {
+        let json = include_str!("../tests/fixtures/fractional_outputs.json");
+        let outputs = collect_outputs_from_json(json.as_bytes()).unwrap();
+        let render = |refresh_precision, scale_format| {
+            render_outputs_with_options(
+                OutputFormat::Kanshi,
+                "docked",
+                &outputs,
+                &RenderOptions {
+                    refresh_precision,
+                    scale_format,
+                    ..RenderOptions::default()
+                },
+            )
+            .unwrap()
+        };
 
-        let err = upsert_profile_in_config(current, "broken", inserted).unwrap_err();
-        assert!(matches!(err, GenerateError::ConfigParse { .. }));
+        assert_eq!(
+            render(RefreshPrecision::default(), ScaleFormat::default()),
+            include_str!("../tests/fixtures/fractional_outputs.kanshi")
+        );
+        assert_eq!(
+            render(RefreshPrecision::Exact, ScaleFormat::Shortest),
+            include_str!("../tests/fixtures/fractional_outputs.exact.kanshi")
+        );
+
+        let three_decimals = render(RefreshPrecision::ThreeDecimals, ScaleFormat::Fixed);
+        assert!(three_decimals.contains("mode 2880x1920@120.000Hz position 0,0 scale 1.33\n"));
+        assert!(three_decimals.contains("mode 2560x1440@143.856Hz"));
+        let rounded = render(RefreshPrecision::Rounded, ScaleFormat::Fixed);
+        assert!(rounded.contains("mode 2560x1440@144Hz position 2160,0 scale 1.00\n"));
     }
 
     #[test]
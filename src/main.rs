@@ -1,29 +1,72 @@
 use std::{
-    fs,
-    io::{self, Read as _},
-    path::PathBuf,
+    env, fs,
+    io::{self, IsTerminal as _, Read as _},
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
-use clap::Parser;
-use color_eyre::{Result, eyre::Context as _};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use color_eyre::{
+    Result,
+    eyre::{Context as _, bail},
+};
 use kanshi_generate::{
-    UpsertOutcome, collect_outputs_wayland, generate_profile_from_outputs,
-    generate_profile_from_slice, resolve_default_kanshi_config_path,
-    upsert_profile_in_file_with_outcome,
+    ConfigWriteOptions, InputFormat, MatchBy, OutputFormat, OutputSnapshot, ProfileSettings,
+    ProfileWatcher, RefreshPrecision, RenderOptions, ScaleFormat, UpsertOutcome, Wildcard,
+    apply_profile_wayland, collect_outputs_from_json, collect_outputs_from_json_as,
+    collect_outputs_wayland, dump_outputs_to_json, filter_outputs, list_config_backups,
+    list_profiles_in_file, preview_upsert_profile_in_file, remove_profile_from_file,
+    rename_profile_in_file, render_outputs_with_options, resolve_default_kanshi_config_path,
+    restore_config_backup, show_profile_in_file, test_profile_wayland,
+    upsert_profile_in_file_with_options, watch_outputs_wayland,
 };
 
+/// Backups kept by the CLI; the library only backs up when asked to.
+const DEFAULT_BACKUP_COUNT: usize = 5;
+
 #[derive(Debug, Parser)]
 #[command(
-    about = "Generate a kanshi profile from Wayland output-management state",
+    about = "Generate and manage kanshi profiles from Wayland output-management state",
     version,
     author
 )]
 struct Arguments {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Generate a profile from the current outputs and upsert it into the kanshi config
+    Generate(GenerateArguments),
+    /// List the profile names in the kanshi config
+    List(ConfigArguments),
+    /// Print a profile block from the kanshi config
+    Show(ShowArguments),
+    /// Remove a profile from the kanshi config
+    Delete(DeleteArguments),
+    /// Rename a profile in the kanshi config
+    Rename(RenameArguments),
+    /// List config backups, or restore one of them
+    Restore(RestoreArguments),
+    /// Apply a profile to the running compositor without waiting for kanshi
+    Apply(ApplyArguments),
+    /// Keep running and regenerate the profile whenever the outputs change
+    Watch(WatchArguments),
+    /// Print the current outputs as JSON that `--input-json` accepts
+    Dump(DumpArguments),
+}
+
+#[derive(Debug, Args)]
+struct GenerateArguments {
     /// Profile name
     name: String,
     /// Read JSON from a file path or '-' for stdin instead of querying Wayland output-management protocol
     #[arg(long, value_name = "PATH")]
     input_json: Option<String>,
+    /// Schema of the JSON input (default: detected from the input)
+    #[arg(long, value_name = "FORMAT", requires = "input_json")]
+    input_format: Option<InputFormatArgument>,
     /// Override kanshi config file path (default: $XDG_CONFIG_HOME/kanshi/config or $HOME/.config/kanshi/config)
     #[arg(
         long,
@@ -38,6 +81,294 @@ struct Arguments {
     /// Write generated profile to a file path (raw mode, no config parsing/upsert)
     #[arg(long, value_name = "PATH")]
     output: Option<PathBuf>,
+    /// Ask the compositor to test the generated outputs before writing anything
+    #[arg(long)]
+    verify: bool,
+    /// Print a unified diff of the config change instead of writing it
+    #[arg(
+        long,
+        visible_alias = "diff",
+        conflicts_with = "stdout",
+        conflicts_with = "output"
+    )]
+    dry_run: bool,
+    /// Configuration syntax to generate; formats other than kanshi require --stdout or --output
+    #[arg(long, value_name = "FORMAT", default_value = "kanshi")]
+    format: OutputFormatArgument,
+    /// How kanshi profile outputs identify monitors (default: auto)
+    #[arg(long, value_name = "STRATEGY")]
+    match_by: Option<MatchByArgument>,
+    /// Write this output (connector name or identifier), or every non-built-in output with `external`, as `output *`
+    #[arg(long, value_name = "OUTPUT")]
+    wildcard: Option<String>,
+    /// Only include this output (connector name or identifier); repeat for several outputs
+    #[arg(long, value_name = "OUTPUT")]
+    only: Vec<String>,
+    /// Leave out this output (connector name or identifier); repeat for several outputs
+    #[arg(long, value_name = "OUTPUT")]
+    exclude: Vec<String>,
+    /// How refresh rates are written in kanshi modes (default: two-decimals)
+    #[arg(long, value_name = "PRECISION")]
+    refresh_precision: Option<RefreshPrecisionArgument>,
+    /// How scale factors are written in kanshi profiles (default: fixed)
+    #[arg(long, value_name = "FORMAT")]
+    scale_format: Option<ScaleFormatArgument>,
+    #[command(flatten)]
+    write: WriteArguments,
+}
+
+#[derive(Debug, Args)]
+struct WriteArguments {
+    /// Number of timestamped config backups to keep (0 disables backups)
+    #[arg(long, value_name = "COUNT", default_value_t = DEFAULT_BACKUP_COUNT)]
+    backups: usize,
+    /// Seconds to wait for another kanshi-generate process to release the config lock
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = ConfigWriteOptions::default().lock_timeout.as_secs()
+    )]
+    lock_timeout: u64,
+    /// Redo the merge up to COUNT times if another program changes the config while it is being updated
+    #[arg(long, value_name = "COUNT", default_value_t = ConfigWriteOptions::default().modification_retries)]
+    retries: usize,
+}
+
+#[derive(Debug, Args)]
+struct ConfigArguments {
+    /// Override kanshi config file path (default: $XDG_CONFIG_HOME/kanshi/config or $HOME/.config/kanshi/config)
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct ShowArguments {
+    /// Profile name
+    name: String,
+    #[command(flatten)]
+    config: ConfigArguments,
+}
+
+#[derive(Debug, Args)]
+struct DeleteArguments {
+    /// Profile name
+    name: String,
+    #[command(flatten)]
+    config: ConfigArguments,
+    #[command(flatten)]
+    write: WriteArguments,
+}
+
+#[derive(Debug, Args)]
+struct RenameArguments {
+    /// Current profile name
+    name: String,
+    /// New profile name
+    new_name: String,
+    #[command(flatten)]
+    config: ConfigArguments,
+    #[command(flatten)]
+    write: WriteArguments,
+}
+
+#[derive(Debug, Args)]
+struct RestoreArguments {
+    /// Backup to restore: its timestamp, its file path, or `latest` (default: list backups)
+    backup: Option<String>,
+    #[command(flatten)]
+    config: ConfigArguments,
+    #[command(flatten)]
+    write: WriteArguments,
+}
+
+#[derive(Debug, Args)]
+struct ApplyArguments {
+    /// Name of the profile in the kanshi config to apply
+    #[arg(required_unless_present = "input_json", conflicts_with = "input_json")]
+    name: Option<String>,
+    /// Apply output state captured as JSON (file path or '-' for stdin) instead of a config profile
+    #[arg(long, value_name = "PATH")]
+    input_json: Option<String>,
+    /// Schema of the JSON input (default: detected from the input)
+    #[arg(long, value_name = "FORMAT", requires = "input_json")]
+    input_format: Option<InputFormatArgument>,
+    /// Override kanshi config file path (default: $XDG_CONFIG_HOME/kanshi/config or $HOME/.config/kanshi/config)
+    #[arg(long, value_name = "PATH", conflicts_with = "input_json")]
+    config: Option<PathBuf>,
+    /// Only test whether the compositor would accept the configuration
+    #[arg(long)]
+    verify: bool,
+}
+
+#[derive(Debug, Args)]
+struct WatchArguments {
+    /// Profile name
+    name: String,
+    /// Override kanshi config file path (default: $XDG_CONFIG_HOME/kanshi/config or $HOME/.config/kanshi/config)
+    #[arg(long, value_name = "PATH", conflicts_with = "stdout")]
+    config: Option<PathBuf>,
+    /// Print each regenerated profile to stdout instead of updating the config
+    #[arg(long)]
+    stdout: bool,
+    /// How kanshi profile outputs identify monitors (default: auto)
+    #[arg(long, value_name = "STRATEGY")]
+    match_by: Option<MatchByArgument>,
+    /// Write this output (connector name or identifier), or every non-built-in output with `external`, as `output *`
+    #[arg(long, value_name = "OUTPUT")]
+    wildcard: Option<String>,
+    /// Only include this output (connector name or identifier); repeat for several outputs
+    #[arg(long, value_name = "OUTPUT")]
+    only: Vec<String>,
+    /// Leave out this output (connector name or identifier); repeat for several outputs
+    #[arg(long, value_name = "OUTPUT")]
+    exclude: Vec<String>,
+    /// How refresh rates are written in kanshi modes (default: two-decimals)
+    #[arg(long, value_name = "PRECISION")]
+    refresh_precision: Option<RefreshPrecisionArgument>,
+    /// How scale factors are written in kanshi profiles (default: fixed)
+    #[arg(long, value_name = "FORMAT")]
+    scale_format: Option<ScaleFormatArgument>,
+    #[command(flatten)]
+    write: WriteArguments,
+}
+
+#[derive(Debug, Args)]
+struct DumpArguments {
+    /// Convert JSON from a file path or '-' for stdin instead of querying Wayland output-management protocol
+    #[arg(long, value_name = "PATH")]
+    input_json: Option<String>,
+    /// Schema of the JSON input (default: detected from the input)
+    #[arg(long, value_name = "FORMAT", requires = "input_json")]
+    input_format: Option<InputFormatArgument>,
+    /// Write the JSON to a file path instead of stdout
+    #[arg(long, value_name = "PATH")]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormatArgument {
+    /// kanshi profile block
+    Kanshi,
+    /// sway `output` commands
+    Sway,
+    /// Hyprland `monitor=` lines
+    Hyprland,
+    /// niri KDL `output` blocks
+    Niri,
+    /// a single `wlr-randr` command line
+    WlrRandr,
+}
+
+impl From<OutputFormatArgument> for OutputFormat {
+    fn from(format: OutputFormatArgument) -> Self {
+        match format {
+            OutputFormatArgument::Kanshi => Self::Kanshi,
+            OutputFormatArgument::Sway => Self::Sway,
+            OutputFormatArgument::Hyprland => Self::Hyprland,
+            OutputFormatArgument::Niri => Self::Niri,
+            OutputFormatArgument::WlrRandr => Self::WlrRandr,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum MatchByArgument {
+    /// make, model and serial when known, otherwise the connector name
+    Auto,
+    /// make, model and serial; fails for outputs without them
+    Description,
+    /// connector name such as `DP-1`
+    Connector,
+    /// make and model with a wildcard serial, for fleets of identical monitors
+    MakeModel,
+}
+
+impl From<MatchByArgument> for MatchBy {
+    fn from(match_by: MatchByArgument) -> Self {
+        match match_by {
+            MatchByArgument::Auto => Self::Auto,
+            MatchByArgument::Description => Self::Description,
+            MatchByArgument::Connector => Self::Connector,
+            MatchByArgument::MakeModel => Self::MakeModel,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum RefreshPrecisionArgument {
+    /// two decimals, e.g. `59.95Hz`
+    TwoDecimals,
+    /// three decimals, e.g. `59.951Hz`
+    ThreeDecimals,
+    /// exact millihertz value without trailing zeros, e.g. `59.951Hz` or `60Hz`
+    Exact,
+    /// whole hertz, e.g. `60Hz`
+    Rounded,
+}
+
+impl From<RefreshPrecisionArgument> for RefreshPrecision {
+    fn from(precision: RefreshPrecisionArgument) -> Self {
+        match precision {
+            RefreshPrecisionArgument::TwoDecimals => Self::TwoDecimals,
+            RefreshPrecisionArgument::ThreeDecimals => Self::ThreeDecimals,
+            RefreshPrecisionArgument::Exact => Self::Exact,
+            RefreshPrecisionArgument::Rounded => Self::Rounded,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ScaleFormatArgument {
+    /// two decimals, e.g. `1.33`
+    Fixed,
+    /// shortest exact representation, e.g. `1.333333` or `1`
+    Shortest,
+}
+
+impl From<ScaleFormatArgument> for ScaleFormat {
+    fn from(format: ScaleFormatArgument) -> Self {
+        match format {
+            ScaleFormatArgument::Fixed => Self::Fixed,
+            ScaleFormatArgument::Shortest => Self::Shortest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum InputFormatArgument {
+    /// wlr-randr style JSON (`wlr-randr --json`)
+    Wlr,
+    /// `swaymsg -t get_outputs --raw`
+    Sway,
+    /// `hyprctl monitors all -j`
+    Hyprland,
+}
+
+impl From<&WriteArguments> for ConfigWriteOptions {
+    fn from(args: &WriteArguments) -> Self {
+        Self {
+            backup_count: args.backups,
+            lock_timeout: Duration::from_secs(args.lock_timeout),
+            modification_retries: args.retries,
+        }
+    }
+}
+
+impl From<InputFormatArgument> for InputFormat {
+    fn from(format: InputFormatArgument) -> Self {
+        match format {
+            InputFormatArgument::Wlr => Self::Wlr,
+            InputFormatArgument::Sway => Self::Sway,
+            InputFormatArgument::Hyprland => Self::Hyprland,
+        }
+    }
+}
+
+fn parse_wildcard(wildcard: &str) -> Wildcard {
+    match wildcard {
+        "external" => Wildcard::External,
+        output => Wildcard::Output(output.to_owned()),
+    }
 }
 
 fn read_input(input_json: &str) -> Result<Vec<u8>> {
@@ -53,56 +384,289 @@ fn read_input(input_json: &str) -> Result<Vec<u8>> {
     }
 }
 
-fn write_raw_output(kanshi: &str, output: Option<&PathBuf>) -> Result<()> {
-    match output {
-        None => {
-            print!("{kanshi}");
-            Ok(())
-        }
-        Some(path) => fs::write(path, kanshi)
-            .wrap_err_with(|| format!("failed to write generated profile to `{}`", path.display())),
+fn read_outputs(
+    input_json: &str,
+    input_format: Option<InputFormatArgument>,
+) -> Result<Vec<OutputSnapshot>> {
+    let raw_json = read_input(input_json)?;
+    let outputs = match input_format {
+        Some(format) => collect_outputs_from_json_as(&raw_json, format.into()),
+        None => collect_outputs_from_json(&raw_json),
+    };
+    outputs.wrap_err("failed to read output state from JSON input")
+}
+
+fn write_raw_output(
+    rendered: &str,
+    output: Option<&PathBuf>,
+    format: OutputFormatArgument,
+) -> Result<()> {
+    let Some(path) = output else {
+        print!("{rendered}");
+        return Ok(());
+    };
+
+    let write_error = || format!("failed to write generated profile to `{}`", path.display());
+    if format != OutputFormatArgument::WlrRandr {
+        return fs::write(path, rendered).wrap_err_with(write_error);
     }
+    // Written to a file, the wlr-randr command becomes a runnable script.
+    fs::write(path, format!("#!/bin/sh\n{rendered}")).wrap_err_with(write_error)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).wrap_err_with(write_error)?;
+    }
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    color_eyre::install()?;
+/// Prints a unified diff, colored when stdout is a terminal and `NO_COLOR` is unset.
+fn print_diff(diff: &str) {
+    let color = io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    if !color {
+        print!("{diff}");
+        return;
+    }
+
+    for line in diff.split_inclusive('\n') {
+        let style = if line.starts_with("+++") || line.starts_with("---") {
+            "1"
+        } else if line.starts_with("@@") {
+            "36"
+        } else if line.starts_with('+') {
+            "32"
+        } else if line.starts_with('-') {
+            "31"
+        } else {
+            print!("{line}");
+            continue;
+        };
+        let (text, newline) = match line.strip_suffix('\n') {
+            Some(text) => (text, "\n"),
+            None => (line, ""),
+        };
+        print!("\x1b[{style}m{text}\x1b[0m{newline}");
+    }
+}
+
+fn resolve_config_path(config: Option<PathBuf>) -> Result<PathBuf> {
+    match config {
+        Some(path) => Ok(path),
+        None => resolve_default_kanshi_config_path()
+            .wrap_err("failed to resolve default kanshi config path"),
+    }
+}
+
+fn apply(args: ApplyArguments) -> Result<()> {
+    let (profile, source) = if let Some(input_json) = args.input_json.as_deref() {
+        let outputs = read_outputs(input_json, args.input_format)?;
+        (
+            ProfileSettings::from_outputs(&outputs),
+            format!("output state from `{input_json}`"),
+        )
+    } else {
+        let name = args
+            .name
+            .expect("clap requires a profile name without --input-json");
+        let config_path = resolve_config_path(args.config)?;
+        let config = fs::read_to_string(&config_path).wrap_err_with(|| {
+            format!("failed to read kanshi config `{}`", config_path.display())
+        })?;
+        let profile = ProfileSettings::from_config(&config, &name)
+            .wrap_err_with(|| format!("failed to load profile from `{}`", config_path.display()))?;
+        (profile, format!("profile `{name}`"))
+    };
 
-    let args = Arguments::parse();
-    let kanshi = if let Some(input_json) = args.input_json.as_deref() {
-        let raw_json = read_input(input_json)?;
-        generate_profile_from_slice(&args.name, &raw_json)
-            .wrap_err("failed to generate kanshi profile from JSON input")?
+    if args.verify {
+        test_profile_wayland(&profile).wrap_err("failed to verify output configuration")?;
+        eprintln!("compositor accepts {source}");
+    } else {
+        apply_profile_wayland(&profile).wrap_err("failed to apply output configuration")?;
+        eprintln!("applied {source}");
+    }
+    Ok(())
+}
+
+fn watch(args: WatchArguments) -> Result<()> {
+    let config_path = if args.stdout {
+        None
+    } else {
+        Some(resolve_config_path(args.config)?)
+    };
+
+    let mut write_options = ConfigWriteOptions::from(&args.write);
+    let render_options = RenderOptions {
+        match_by: args.match_by.map_or_else(MatchBy::default, MatchBy::from),
+        wildcard: args.wildcard.as_deref().map(parse_wildcard),
+        refresh_precision: args
+            .refresh_precision
+            .map_or_else(RefreshPrecision::default, RefreshPrecision::from),
+        scale_format: args
+            .scale_format
+            .map_or_else(ScaleFormat::default, ScaleFormat::from),
+    };
+    let mut watcher = ProfileWatcher::new();
+    watch_outputs_wayland(|outputs| {
+        if !watcher.is_watched_head_set(outputs) {
+            eprintln!(
+                "skipping output update: connected outputs differ from the ones profile `{}` was started with",
+                args.name
+            );
+            return;
+        }
+        let kanshi = match filter_outputs(outputs, &args.only, &args.exclude).and_then(|outputs| {
+            render_outputs_with_options(OutputFormat::Kanshi, &args.name, &outputs, &render_options)
+        }) {
+            Ok(kanshi) => kanshi,
+            Err(error) => {
+                eprintln!("skipping output update: {error}");
+                return;
+            }
+        };
+        if !watcher.is_new_profile(&kanshi) {
+            return;
+        }
+
+        match config_path.as_ref() {
+            None => print!("{kanshi}"),
+            Some(config_path) => {
+                match upsert_profile_in_file_with_options(
+                    config_path,
+                    &args.name,
+                    &kanshi,
+                    &write_options,
+                ) {
+                    Ok(_) => {
+                        eprintln!(
+                            "updated kanshi config `{}`: profile `{}`",
+                            config_path.display(),
+                            args.name
+                        );
+                        // Only the config from before the watch is worth
+                        // undoing to; don't let later regenerations rotate it out.
+                        write_options.backup_count = 0;
+                    }
+                    Err(error) => {
+                        eprintln!(
+                            "failed to update kanshi config `{}`: {error}",
+                            config_path.display()
+                        );
+                        return;
+                    }
+                }
+            }
+        }
+        watcher.profile_written(kanshi);
+    })
+    .wrap_err("failed to watch output state from Wayland protocol")
+}
+
+fn generate(args: GenerateArguments) -> Result<()> {
+    let name = args.name;
+    let raw_mode = args.stdout || args.output.is_some();
+    if args.format != OutputFormatArgument::Kanshi && !raw_mode {
+        bail!(
+            "`--format {}` cannot be merged into the kanshi config; use --stdout or --output",
+            args.format
+                .to_possible_value()
+                .expect("output formats are not skipped")
+                .get_name()
+        );
+    }
+    if args.format != OutputFormatArgument::Kanshi {
+        let kanshi_only = [
+            ("--match-by", args.match_by.is_some()),
+            ("--wildcard", args.wildcard.is_some()),
+            ("--refresh-precision", args.refresh_precision.is_some()),
+            ("--scale-format", args.scale_format.is_some()),
+        ];
+        if let Some((flag, _)) = kanshi_only.iter().find(|(_, used)| *used) {
+            bail!("`{flag}` only applies to `--format kanshi`");
+        }
+    }
+    let render_options = RenderOptions {
+        match_by: args.match_by.map_or_else(MatchBy::default, MatchBy::from),
+        wildcard: args.wildcard.as_deref().map(parse_wildcard),
+        refresh_precision: args
+            .refresh_precision
+            .map_or_else(RefreshPrecision::default, RefreshPrecision::from),
+        scale_format: args
+            .scale_format
+            .map_or_else(ScaleFormat::default, ScaleFormat::from),
+    };
+
+    let (outputs, rendered) = if let Some(input_json) = args.input_json.as_deref() {
+        let outputs = read_outputs(input_json, args.input_format)?;
+        let outputs = filter_outputs(&outputs, &args.only, &args.exclude)
+            .wrap_err("failed to select outputs from JSON input")?;
+        let rendered =
+            render_outputs_with_options(args.format.into(), &name, &outputs, &render_options)
+                .wrap_err("failed to generate profile from JSON input")?;
+        (outputs, rendered)
     } else {
         let outputs = collect_outputs_wayland()
             .wrap_err("failed to collect output state from Wayland protocol")?;
-        generate_profile_from_outputs(&args.name, &outputs)
-            .wrap_err("failed to generate kanshi profile from Wayland state")?
+        let outputs = filter_outputs(&outputs, &args.only, &args.exclude)
+            .wrap_err("failed to select outputs from Wayland state")?;
+        let rendered =
+            render_outputs_with_options(args.format.into(), &name, &outputs, &render_options)
+                .wrap_err("failed to generate profile from Wayland state")?;
+        (outputs, rendered)
     };
 
-    if args.stdout || args.output.is_some() {
-        write_raw_output(&kanshi, args.output.as_ref())?;
+    if args.verify {
+        // Test what is actually written: kanshi profiles carry the chosen
+        // criteria and refresh/scale precision, other formats the captured state.
+        let profile = if args.format == OutputFormatArgument::Kanshi {
+            ProfileSettings::from_config(&rendered, &name)
+                .wrap_err("failed to read back generated profile")?
+        } else {
+            ProfileSettings::from_outputs(&outputs)
+        };
+        test_profile_wayland(&profile)
+            .wrap_err("failed to verify generated profile with the compositor")?;
+    }
+
+    if raw_mode {
+        write_raw_output(&rendered, args.output.as_ref(), args.format)?;
         if let Some(output_path) = args.output.as_ref() {
             eprintln!(
                 "wrote generated profile `{}` to `{}`",
-                args.name,
+                name,
                 output_path.display()
             );
         }
         return Ok(());
     }
 
-    let config_path = match args.config {
-        Some(path) => path,
-        None => resolve_default_kanshi_config_path()
-            .wrap_err("failed to resolve default kanshi config path")?,
-    };
-    let outcome = upsert_profile_in_file_with_outcome(&config_path, &args.name, &kanshi)
-        .wrap_err_with(|| {
-            format!(
-                "failed to update kanshi config at `{}`",
+    let config_path = resolve_config_path(args.config)?;
+    if args.dry_run {
+        let preview = preview_upsert_profile_in_file(&config_path, &name, &rendered)
+            .wrap_err_with(|| {
+                format!(
+                    "failed to read kanshi config at `{}`",
+                    config_path.display()
+                )
+            })?;
+        if preview.is_unchanged() {
+            eprintln!(
+                "kanshi config `{}` is already up to date",
                 config_path.display()
-            )
-        })?;
+            );
+        } else {
+            print_diff(&preview.unified_diff());
+        }
+        return Ok(());
+    }
+
+    let outcome =
+        upsert_profile_in_file_with_options(&config_path, &name, &rendered, &(&args.write).into())
+            .wrap_err_with(|| {
+                format!(
+                    "failed to update kanshi config at `{}`",
+                    config_path.display()
+                )
+            })?;
     let action = match outcome {
         UpsertOutcome::ReplacedExisting => "replaced existing profile",
         UpsertOutcome::AppendedNew => "appended new profile",
@@ -111,7 +675,155 @@ fn main() -> Result<()> {
         "updated kanshi config `{}`: {} `{}`",
         config_path.display(),
         action,
+        name
+    );
+    Ok(())
+}
+
+fn list(args: ConfigArguments) -> Result<()> {
+    let config_path = resolve_config_path(args.config)?;
+    let names = list_profiles_in_file(&config_path).wrap_err_with(|| {
+        format!(
+            "failed to read kanshi config at `{}`",
+            config_path.display()
+        )
+    })?;
+    for name in names {
+        println!("{name}");
+    }
+    Ok(())
+}
+
+fn show(args: ShowArguments) -> Result<()> {
+    let config_path = resolve_config_path(args.config.config)?;
+    let profile = show_profile_in_file(&config_path, &args.name).wrap_err_with(|| {
+        format!(
+            "failed to read kanshi config at `{}`",
+            config_path.display()
+        )
+    })?;
+    println!("{profile}");
+    Ok(())
+}
+
+fn delete(args: DeleteArguments) -> Result<()> {
+    let config_path = resolve_config_path(args.config.config)?;
+    remove_profile_from_file(&config_path, &args.name, &(&args.write).into()).wrap_err_with(
+        || {
+            format!(
+                "failed to update kanshi config at `{}`",
+                config_path.display()
+            )
+        },
+    )?;
+    eprintln!(
+        "updated kanshi config `{}`: deleted profile `{}`",
+        config_path.display(),
         args.name
     );
     Ok(())
 }
+
+fn rename(args: RenameArguments) -> Result<()> {
+    let config_path = resolve_config_path(args.config.config)?;
+    rename_profile_in_file(
+        &config_path,
+        &args.name,
+        &args.new_name,
+        &(&args.write).into(),
+    )
+    .wrap_err_with(|| {
+        format!(
+            "failed to update kanshi config at `{}`",
+            config_path.display()
+        )
+    })?;
+    eprintln!(
+        "updated kanshi config `{}`: renamed profile `{}` to `{}`",
+        config_path.display(),
+        args.name,
+        args.new_name
+    );
+    Ok(())
+}
+
+fn restore(args: RestoreArguments) -> Result<()> {
+    let config_path = resolve_config_path(args.config.config)?;
+    let backups = list_config_backups(&config_path).wrap_err_with(|| {
+        format!(
+            "failed to list backups of kanshi config `{}`",
+            config_path.display()
+        )
+    })?;
+
+    let Some(selector) = args.backup else {
+        if backups.is_empty() {
+            eprintln!("no backups of kanshi config `{}`", config_path.display());
+        }
+        for backup in &backups {
+            println!("{}\t{}", backup.timestamp(), backup.path().display());
+        }
+        return Ok(());
+    };
+
+    let backup = if selector == "latest" {
+        backups.first()
+    } else {
+        backups
+            .iter()
+            .find(|backup| backup.timestamp() == selector || backup.path() == Path::new(&selector))
+    };
+    let Some(backup) = backup else {
+        bail!(
+            "no backup `{selector}` of kanshi config `{}`; run `kanshi-generate restore` to list backups",
+            config_path.display()
+        );
+    };
+
+    restore_config_backup(&config_path, backup, &(&args.write).into()).wrap_err_with(|| {
+        format!(
+            "failed to update kanshi config at `{}`",
+            config_path.display()
+        )
+    })?;
+    eprintln!(
+        "updated kanshi config `{}`: restored backup `{}`",
+        config_path.display(),
+        backup.timestamp()
+    );
+    Ok(())
+}
+
+fn dump(args: DumpArguments) -> Result<()> {
+    let outputs = match args.input_json.as_deref() {
+        Some(input_json) => read_outputs(input_json, args.input_format)?,
+        None => collect_outputs_wayland()
+            .wrap_err("failed to collect output state from Wayland protocol")?,
+    };
+    let json = dump_outputs_to_json(&outputs).wrap_err("failed to dump output state")?;
+
+    match args.output {
+        None => {
+            print!("{json}");
+            Ok(())
+        }
+        Some(path) => fs::write(&path, json)
+            .wrap_err_with(|| format!("failed to write output state to `{}`", path.display())),
+    }
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+
+    match Arguments::parse().command {
+        Command::Generate(args) => generate(args),
+        Command::List(args) => list(args),
+        Command::Show(args) => show(args),
+        Command::Delete(args) => delete(args),
+        Command::Rename(args) => rename(args),
+        Command::Restore(args) => restore(args),
+        Command::Apply(args) => apply(args),
+        Command::Watch(args) => watch(args),
+        Command::Dump(args) => dump(args),
+    }
+}
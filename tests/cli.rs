@@ -43,7 +43,7 @@ fn cli_default_mode_updates_existing_profile_in_default_config_path() {
     )
     .unwrap();
 
-    let output = run_with_input_json(&["docked"], |command| {
+    let output = run_with_input_json(&["generate", "docked"], |command| {
         command.env("XDG_CONFIG_HOME", xdg_config_home.path());
     });
 
@@ -70,7 +70,12 @@ fn cli_config_flag_updates_given_file_path() {
     .unwrap();
 
     let output = run_with_input_json(
-        &["docked", "--config", config_path.to_str().unwrap()],
+        &[
+            "generate",
+            "docked",
+            "--config",
+            config_path.to_str().unwrap(),
+        ],
         |_| {},
     );
 
@@ -89,7 +94,12 @@ fn cli_appends_profile_if_missing() {
     fs::write(&config_path, "profile alpha {\n  output \"x\" disable\n}\n").unwrap();
 
     let output = run_with_input_json(
-        &["docked", "--config", config_path.to_str().unwrap()],
+        &[
+            "generate",
+            "docked",
+            "--config",
+            config_path.to_str().unwrap(),
+        ],
         |_| {},
     );
 
@@ -110,7 +120,12 @@ fn cli_fails_and_keeps_file_unchanged_for_duplicate_profiles() {
     fs::write(&config_path, initial).unwrap();
 
     let output = run_with_input_json(
-        &["docked", "--config", config_path.to_str().unwrap()],
+        &[
+            "generate",
+            "docked",
+            "--config",
+            config_path.to_str().unwrap(),
+        ],
         |_| {},
     );
 
@@ -128,7 +143,7 @@ fn cli_stdout_mode_prints_profile_and_does_not_edit_config() {
     let initial = "profile docked {\n  output \"old\" disable\n}\n";
     fs::write(&config_path, initial).unwrap();
 
-    let output = run_with_input_json(&["docked", "--stdout"], |command| {
+    let output = run_with_input_json(&["generate", "docked", "--stdout"], |command| {
         command.env("XDG_CONFIG_HOME", xdg_config_home.path());
     });
 
@@ -149,7 +164,7 @@ fn cli_output_mode_writes_raw_profile_without_parsing_config() {
     fs::write(&broken_config, "profile broken {\n  output \"x\" disable\n").unwrap();
 
     let output = run_with_input_json(
-        &["docked", "--output", out_path.to_str().unwrap()],
+        &["generate", "docked", "--output", out_path.to_str().unwrap()],
         |command| {
             command.env("XDG_CONFIG_HOME", xdg_config_home.path());
         },
@@ -166,9 +181,177 @@ fn cli_output_mode_writes_raw_profile_without_parsing_config() {
     );
 }
 
+#[test]
+fn cli_reads_sway_json_with_explicit_and_detected_format() {
+    let expected = fs::read_to_string(fixture_path("sway_outputs.kanshi")).unwrap();
+    let fixture = fixture_path("sway_outputs.json");
+
+    for format_args in [&["--input-format", "sway"][..], &[]] {
+        let output = binary_command()
+            .args(["generate", "docked", "--stdout", "--input-json"])
+            .arg(&fixture)
+            .args(format_args)
+            .output()
+            .unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+    }
+}
+
+#[test]
+fn cli_dump_writes_json_that_generate_replays() {
+    let temp_dir = TempDir::new().unwrap();
+    let dump_path = temp_dir.path().join("outputs.json");
+    let expected = fs::read_to_string(fixture_path("sway_outputs.kanshi")).unwrap();
+
+    let output = binary_command()
+        .args(["dump", "--input-json"])
+        .arg(fixture_path("sway_outputs.json"))
+        .arg("--output")
+        .arg(&dump_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    let output = binary_command()
+        .args([
+            "generate",
+            "docked",
+            "--stdout",
+            "--input-format",
+            "wlr",
+            "--input-json",
+        ])
+        .arg(&dump_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+
+    let output = binary_command()
+        .args(["dump", "--input-json"])
+        .arg(&dump_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        fs::read_to_string(&dump_path).unwrap()
+    );
+}
+
+#[test]
+fn cli_renders_sway_output_commands() {
+    let output = run_with_input_json(
+        &["generate", "docked", "--format", "sway", "--stdout"],
+        |_| {},
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        fs::read_to_string(fixture_path("mixed_outputs.sway")).unwrap()
+    );
+}
+
+#[test]
+fn cli_renders_hyprland_monitor_lines() {
+    let output = run_with_input_json(
+        &["generate", "docked", "--format", "hyprland", "--stdout"],
+        |_| {},
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        fs::read_to_string(fixture_path("mixed_outputs.hyprland")).unwrap()
+    );
+}
+
+#[test]
+fn cli_renders_wlr_randr_command() {
+    let output = run_with_input_json(
+        &["generate", "docked", "--format", "wlr-randr", "--stdout"],
+        |_| {},
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        fs::read_to_string(fixture_path("mixed_outputs.wlr-randr")).unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn cli_writes_wlr_randr_command_as_executable_script() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let temp = TempDir::new().unwrap();
+    let script_path = temp.path().join("docked.sh");
+    let output = run_with_input_json(
+        &[
+            "generate",
+            "docked",
+            "--format",
+            "wlr-randr",
+            "--output",
+            script_path.to_str().unwrap(),
+        ],
+        |_| {},
+    );
+
+    assert!(output.status.success());
+    let expected = fs::read_to_string(fixture_path("mixed_outputs.wlr-randr")).unwrap();
+    assert_eq!(
+        fs::read_to_string(&script_path).unwrap(),
+        format!("#!/bin/sh\n{expected}")
+    );
+    let mode = fs::metadata(&script_path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o755);
+}
+
+#[test]
+fn cli_renders_niri_output_blocks() {
+    let output = run_with_input_json(
+        &["generate", "docked", "--format", "niri", "--stdout"],
+        |_| {},
+    );
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        fs::read_to_string(fixture_path("mixed_outputs.niri")).unwrap()
+    );
+}
+
+#[test]
+fn cli_rejects_non_kanshi_format_for_config_upsert() {
+    let temp = TempDir::new().unwrap();
+    let config_path = temp.path().join("config");
+    let output = run_with_input_json(
+        &[
+            "generate",
+            "docked",
+            "--format",
+            "sway",
+            "--config",
+            config_path.to_str().unwrap(),
+        ],
+        |_| {},
+    );
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("`--format sway` cannot be merged into the kanshi config"));
+    assert!(!config_path.exists());
+}
+
 #[test]
 fn cli_rejects_stdout_and_output_together() {
-    let output = run_with_input_json(&["docked", "--stdout", "--output", "x"], |_| {});
+    let output = run_with_input_json(&["generate", "docked", "--stdout", "--output", "x"], |_| {});
     assert!(!output.status.success());
     let stderr = String::from_utf8_lossy(&output.stderr);
     assert!(stderr.contains("cannot be used with"));
@@ -180,6 +363,7 @@ fn cli_rejects_config_with_stdout() {
     let config_path = temp.path().join("config");
     let output = run_with_input_json(
         &[
+            "generate",
             "docked",
             "--stdout",
             "--config",
@@ -198,6 +382,7 @@ fn cli_rejects_config_with_output() {
     let config_path = temp.path().join("config");
     let output = run_with_input_json(
         &[
+            "generate",
             "docked",
             "--output",
             "x",
@@ -220,6 +405,7 @@ fn cli_errors_for_invalid_json() {
     let mut command = binary_command();
     let output = command
         .args([
+            "generate",
             "docked",
             "--stdout",
             "--input-json",
@@ -242,6 +428,7 @@ fn cli_errors_for_empty_json() {
     let mut command = binary_command();
     let output = command
         .args([
+            "generate",
             "docked",
             "--stdout",
             "--input-json",
@@ -261,7 +448,7 @@ fn cli_live_mode_reports_wayland_connect_error() {
 
     let mut command = binary_command();
     let output = command
-        .args(["docked", "--stdout"])
+        .args(["generate", "docked", "--stdout"])
         .env("XDG_RUNTIME_DIR", runtime.path())
         .env("WAYLAND_DISPLAY", "wayland-not-existing")
         .output()
@@ -273,6 +460,356 @@ fn cli_live_mode_reports_wayland_connect_error() {
     assert!(stderr.contains("failed to connect to Wayland compositor"));
 }
 
+#[test]
+fn cli_verify_fails_without_compositor_and_keeps_config_unchanged() {
+    let runtime = TempDir::new().unwrap();
+    let temp = TempDir::new().unwrap();
+    let config_path = temp.path().join("config");
+    let initial = "profile docked {\n  output \"old\" disable\n}\n";
+    fs::write(&config_path, initial).unwrap();
+
+    let output = run_with_input_json(
+        &[
+            "generate",
+            "docked",
+            "--verify",
+            "--config",
+            config_path.to_str().unwrap(),
+        ],
+        |command| {
+            command
+                .env("XDG_RUNTIME_DIR", runtime.path())
+                .env("WAYLAND_DISPLAY", "wayland-not-existing");
+        },
+    );
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to verify generated profile with the compositor"));
+    assert_eq!(fs::read_to_string(config_path).unwrap(), initial);
+}
+
+#[test]
+fn cli_verify_reads_back_rounded_profile_before_testing_it() {
+    let runtime = TempDir::new().unwrap();
+    let output = binary_command()
+        .args([
+            "generate",
+            "gaming",
+            "--stdout",
+            "--verify",
+            "--refresh-precision",
+            "rounded",
+            "--input-json",
+        ])
+        .arg(fixture_path("fractional_outputs.json"))
+        .env("XDG_RUNTIME_DIR", runtime.path())
+        .env("WAYLAND_DISPLAY", "wayland-not-existing")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!stderr.contains("failed to read back generated profile"));
+    assert!(stderr.contains("failed to verify generated profile with the compositor"));
+}
+
+#[test]
+fn cli_dry_run_prints_diff_without_writing() {
+    let temp = TempDir::new().unwrap();
+    let config_path = temp.path().join("config");
+    let initial = "profile docked {\n  output \"old\" disable\n}\n";
+    fs::write(&config_path, initial).unwrap();
+
+    let output = run_with_input_json(
+        &[
+            "generate",
+            "docked",
+            "--dry-run",
+            "--config",
+            config_path.to_str().unwrap(),
+        ],
+        |_| {},
+    );
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("\n-  output \"old\" disable\n"));
+    assert!(stdout.contains("\n+  output \"AU Optronics 0xD291\" disable\n"));
+    assert!(!stdout.contains('\x1b'));
+    assert_eq!(fs::read_to_string(&config_path).unwrap(), initial);
+}
+
+#[test]
+fn cli_match_by_connector_uses_connector_names() {
+    let output = run_with_input_json(
+        &["generate", "docked", "--stdout", "--match-by", "connector"],
+        |_| {},
+    );
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("  output \"eDP-1\" disable\n"));
+    assert!(stdout.contains("  output \"DP-1\" mode 1920x1080@60.00Hz"));
+
+    let output = run_with_input_json(
+        &[
+            "generate",
+            "docked",
+            "--stdout",
+            "--format",
+            "sway",
+            "--match-by",
+            "connector",
+        ],
+        |_| {},
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("`--match-by` only applies to `--format kanshi`"));
+}
+
+#[test]
+fn cli_wildcard_replaces_selected_output() {
+    let output = run_with_input_json(
+        &["generate", "docked", "--stdout", "--wildcard", "DP-2"],
+        |_| {},
+    );
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("  output \"*\" mode 2560x1440@59.95Hz"));
+
+    let output = run_with_input_json(
+        &["generate", "docked", "--stdout", "--wildcard", "external"],
+        |_| {},
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("can have at most one wildcard output"));
+}
+
+#[test]
+fn cli_only_and_exclude_select_outputs() {
+    let output = run_with_input_json(
+        &[
+            "generate", "docked", "--stdout", "--only", "eDP-1", "--only", "DP-2",
+        ],
+        |_| {},
+    );
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("AU Optronics 0xD291"));
+    assert!(stdout.contains("Dell Inc. DELL P2723D 2ZZ6714"));
+    assert!(!stdout.contains("Dell Inc. DELL U2422H 75BNF83"));
+
+    let output = run_with_input_json(
+        &["generate", "docked", "--stdout", "--exclude", "HDMI-A-1"],
+        |_| {},
+    );
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("output `HDMI-A-1` is not connected"));
+}
+
+#[test]
+fn cli_exact_refresh_and_shortest_scale_match_fixture() {
+    let output = binary_command()
+        .args([
+            "generate",
+            "docked",
+            "--stdout",
+            "--refresh-precision",
+            "exact",
+            "--scale-format",
+            "shortest",
+            "--input-json",
+        ])
+        .arg(fixture_path("fractional_outputs.json"))
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        fs::read_to_string(fixture_path("fractional_outputs.exact.kanshi")).unwrap()
+    );
+}
+
+#[test]
+fn cli_list_and_show_read_profiles_from_config() {
+    let temp = TempDir::new().unwrap();
+    let config_path = temp.path().join("config");
+    fs::write(
+        &config_path,
+        "profile alpha {\n  output \"x\" disable\n}\n\nprofile beta {\n}\n",
+    )
+    .unwrap();
+
+    let output = binary_command()
+        .args(["list", "--config", config_path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "alpha\nbeta\n");
+
+    let output = binary_command()
+        .args(["show", "alpha", "--config", config_path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "profile alpha {\n  output \"x\" disable\n}\n"
+    );
+}
+
+#[test]
+fn cli_delete_and_rename_update_config_in_place() {
+    let temp = TempDir::new().unwrap();
+    let config_path = temp.path().join("config");
+    fs::write(
+        &config_path,
+        "profile alpha {\n}\n\nprofile beta {\n}\n\nprofile gamma {\n}\n",
+    )
+    .unwrap();
+
+    let output = binary_command()
+        .args(["delete", "beta", "--config", config_path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("deleted profile `beta`"));
+
+    let output = binary_command()
+        .args([
+            "rename",
+            "gamma",
+            "docked",
+            "--config",
+            config_path.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        fs::read_to_string(&config_path).unwrap(),
+        "profile alpha {\n}\n\nprofile docked {\n}\n"
+    );
+
+    let output = binary_command()
+        .args([
+            "rename",
+            "alpha",
+            "docked",
+            "--config",
+            config_path.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("profile `docked` already exists in kanshi config"));
+}
+
+#[test]
+fn cli_restore_lists_and_restores_backups() {
+    let temp = TempDir::new().unwrap();
+    let config_path = temp.path().join("config");
+    let initial = "profile docked {\n  output \"old\" disable\n}\n";
+    fs::write(&config_path, initial).unwrap();
+
+    let output = run_with_input_json(
+        &[
+            "generate",
+            "docked",
+            "--config",
+            config_path.to_str().unwrap(),
+        ],
+        |_| {},
+    );
+    assert!(output.status.success());
+    assert_eq!(fs::read_to_string(&config_path).unwrap(), expected_output());
+
+    let output = binary_command()
+        .args(["restore", "--config", config_path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 1);
+    assert!(stdout.contains("config.kanshi-generate.bak."));
+
+    let output = binary_command()
+        .args([
+            "restore",
+            "latest",
+            "--config",
+            config_path.to_str().unwrap(),
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("restored backup"));
+    assert_eq!(fs::read_to_string(&config_path).unwrap(), initial);
+}
+
+#[test]
+fn cli_apply_reports_missing_profile_before_connecting() {
+    let temp = TempDir::new().unwrap();
+    let config_path = temp.path().join("config");
+    fs::write(&config_path, "profile alpha {\n  output \"x\" disable\n}\n").unwrap();
+
+    let output = binary_command()
+        .args(["apply", "docked", "--config", config_path.to_str().unwrap()])
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("profile `docked` not found in kanshi config"));
+}
+
+#[test]
+fn cli_apply_json_reports_wayland_connect_error() {
+    let runtime = TempDir::new().unwrap();
+
+    let output = binary_command()
+        .arg("apply")
+        .arg("--input-json")
+        .arg(fixture_path("mixed_outputs.json"))
+        .env("XDG_RUNTIME_DIR", runtime.path())
+        .env("WAYLAND_DISPLAY", "wayland-not-existing")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to apply output configuration"));
+    assert!(stderr.contains("failed to connect to Wayland compositor"));
+}
+
+#[test]
+fn cli_watch_reports_wayland_connect_error() {
+    let runtime = TempDir::new().unwrap();
+
+    let output = binary_command()
+        .args(["watch", "docked", "--stdout"])
+        .env("XDG_RUNTIME_DIR", runtime.path())
+        .env("WAYLAND_DISPLAY", "wayland-not-existing")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to watch output state from Wayland protocol"));
+    assert!(stderr.contains("failed to connect to Wayland compositor"));
+}
+
 #[cfg(unix)]
 #[test]
 fn cli_updates_symlink_target_without_replacing_symlink() {
@@ -289,7 +826,15 @@ fn cli_updates_symlink_target_without_replacing_symlink() {
     let link_path = temp.path().join("config-link");
     symlink(&target_path, &link_path).unwrap();
 
-    let output = run_with_input_json(&["docked", "--config", link_path.to_str().unwrap()], |_| {});
+    let output = run_with_input_json(
+        &[
+            "generate",
+            "docked",
+            "--config",
+            link_path.to_str().unwrap(),
+        ],
+        |_| {},
+    );
 
     assert!(output.status.success());
     assert!(